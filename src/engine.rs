@@ -0,0 +1,346 @@
+use crate::{
+    aqkanji2koe::{AqK2Kinstance, ReadingCache},
+    aquestalk1::{Aq1Error, AqDLL, AqWAV},
+    wav::{OwnedWav, WavError},
+};
+use std::{borrow::Cow, fs, path::Path};
+
+/// # 読み込み済みのAquesTalkとAqKanji2Koeをまとめて扱うためのラッパー
+/// AqKanji2Koeのインスタンスを設定すると漢字かな混じりのテキストを音声記号列に変換してから合成し､設定しなければテキストを音声記号列としてそのまま合成します
+/// 発話速度はAqDLLのset_default_speedとset_speed_policyの設定をそのまま使い､変換結果はwith_cacheで設定した件数までReadingCacheにキャッシュします
+/// 音声の再生機能は持っていないので､sayで再生したい場合はwith_playerで好きなライブラリに渡す処理を設定してください
+pub struct TtsEngine<'a> {
+    dll: AqDLL<'a>,
+    kanji2koe: Option<ReadingCache<'a>>,
+    cache_capacity: usize,
+    preprocessor: Option<Preprocessor<'a>>,
+    player: Option<Player<'a>>,
+}
+
+/// TtsEngineがテキストを変換する前にかける処理の型です
+pub type Preprocessor<'a> = Box<dyn Fn(&str) -> Cow<str> + Send + Sync + 'a>;
+
+/// TtsEngine::sayが合成したWAVデータを再生する処理の型です
+pub type Player<'a> = Box<dyn FnMut(&[u8]) -> Result<(), Box<dyn std::error::Error>> + Send + 'a>;
+
+impl<'a> TtsEngine<'a> {
+    /// 読み込み済みのAquesTalk.dllから作ります｡発話速度はdllのdefault_speedになります
+    pub fn new(dll: AqDLL<'a>) -> Self {
+        TtsEngine {
+            dll,
+            kanji2koe: None,
+            cache_capacity: 0,
+            preprocessor: None,
+            player: None,
+        }
+    }
+
+    /// テキストを音声記号列に変換するためのAqKanji2Koeのインスタンスを設定します
+    pub fn with_kanji2koe(mut self, instance: AqK2Kinstance<'a>) -> Self {
+        self.kanji2koe = Some(ReadingCache::new(instance, self.cache_capacity));
+        self
+    }
+
+    /// 変換結果をcapacity件までキャッシュするようにします｡0にするとキャッシュしません(初期設定は0です)
+    /// 設定するとそれまでのキャッシュは捨てます
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self.kanji2koe = self
+            .kanji2koe
+            .map(|cache| ReadingCache::new(cache.into_inner(), capacity));
+        self
+    }
+
+    /// テキストを音声記号列に変換する前にかける処理を設定します
+    /// 絵文字の除去や略語の展開など､テキストの下ごしらえを1か所にまとめるのに使えます｡書き換える必要がなければCow::Borrowedを返してください
+    pub fn with_preprocessor<F>(mut self, preprocessor: F) -> Self
+    where
+        F: Fn(&str) -> Cow<str> + Send + Sync + 'a,
+    {
+        self.preprocessor = Some(Box::new(preprocessor));
+        self
+    }
+
+    /// sayで合成したWAVデータを再生する処理を設定します
+    pub fn with_player<F>(mut self, player: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Result<(), Box<dyn std::error::Error>> + Send + 'a,
+    {
+        self.player = Some(Box::new(player));
+        self
+    }
+
+    /// 発話速度を50-300で設定します｡AqDLL::set_default_speedと同じで､範囲外の値を指定した場合はエラーを返し､設定は変わりません
+    pub fn set_speed(&mut self, speed: i32) -> Result<(), Aq1Error> {
+        self.dll.set_default_speed(speed)
+    }
+
+    /// 現在の発話速度を返します
+    pub fn speed(&self) -> i32 {
+        self.dll.default_speed()
+    }
+
+    /// 持っているAqDLLを返します｡set_speed_policyなど､合成の設定を変えるのに使います
+    pub fn dll_mut(&mut self) -> &mut AqDLL<'a> {
+        &mut self.dll
+    }
+
+    /// AqKanji2Koeのインスタンスが設定されていればテキストを音声記号列に変換し､設定されていなければそのまま返します
+    /// with_preprocessorで処理を設定していれば､先にそれをかけます
+    pub fn to_koe(&mut self, text: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let text = match &self.preprocessor {
+            Some(p) => p(text),
+            None => Cow::Borrowed(text),
+        };
+        let text = &*text;
+        match &mut self.kanji2koe {
+            Some(k) => k.convert_cached(text),
+            None => Ok(text.to_string()),
+        }
+    }
+
+    /// テキストを音声記号列に変換してから合成し､WAVデータを返します
+    pub fn speak<'b>(&mut self, text: &str) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+        let koe = self.to_koe(text)?;
+        self.dll.synthe_default(&koe)
+    }
+
+    /// speakで合成した音声を､with_playerで設定した処理で再生します
+    /// 再生する処理を設定していない場合はEngineError::NoPlayerを返します
+    pub fn say(&mut self, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.player.is_none() {
+            return Err(Box::new(EngineError::NoPlayer));
+        }
+        let wav = self.speak(text)?;
+        match &mut self.player {
+            Some(player) => player(&wav),
+            None => Err(Box::new(EngineError::NoPlayer)),
+        }
+    }
+
+    /// speakと同じですが､途中で使った音声記号列も一緒に返します
+    /// 発音がおかしいときに､AqKanji2Koeがどんな音声記号列を出したかを確かめて辞書を調整するのに使えます｡発話速度はspeedで指定します
+    pub fn speak_debug(
+        &mut self,
+        text: &str,
+        speed: i32,
+    ) -> Result<(OwnedWav, String), Box<dyn std::error::Error>> {
+        let koe = self.to_koe(text)?;
+        let wav = self.dll.synthe(&koe, speed)?.to_owned_wav()?;
+        Ok((wav, koe))
+    }
+
+    /// 短い決まった文を変換して合成し､空でない正しいWAVデータができるかを確かめます
+    /// DLLや辞書が壊れている場合はそのエラーを返し､音声が空の場合はWavError::TooShortを返すので､サーバーの起動確認(readiness probe)などに使えます
+    /// AqKanji2Koeの変換は&mut selfを取るので､このメソッドも&mut selfを取ります｡with_preprocessorで設定した処理とキャッシュは使いません
+    pub fn health_check(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let koe = match &mut self.kanji2koe {
+            Some(k) => k.instance().convert("テスト", None)?.to_string(),
+            None => "てすと".to_string(),
+        };
+        let wav = self.dll.synthe_default(&koe)?.to_owned_wav()?;
+        if wav.header().data_len == 0 {
+            return Err(Box::new(WavError::TooShort));
+        }
+        Ok(())
+    }
+
+    /// speakで合成した音声をWAVファイルとしてpathに書き出します
+    pub fn say_to_file<P: AsRef<Path>>(
+        &mut self,
+        text: &str,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let wav = self.speak(text)?;
+        fs::write(path, &*wav)?;
+        Ok(())
+    }
+}
+
+/// # AqKanji2KoeとAquesTalkを1つずつ持ち､変換と合成のバッファーを使い回すためのラッパー
+/// 大量のテキストを次々に音声にする場合に､呼び出しごとのメモリの確保をなるべく減らします
+pub struct Session<'a> {
+    kanji2koe: AqK2Kinstance<'a>,
+    dll: AqDLL<'a>,
+    koe: Vec<u8>,
+}
+
+impl<'a> Session<'a> {
+    /// AqKanji2KoeのインスタンスとAquesTalk.dllから作ります
+    pub fn new(kanji2koe: AqK2Kinstance<'a>, dll: AqDLL<'a>) -> Self {
+        Session {
+            kanji2koe,
+            dll,
+            koe: Vec::new(),
+        }
+    }
+
+    /// テキストを音声記号列に変換してから発話速度speedで合成し､WAVデータをoutに書き込みます
+    /// outの中身は書き込む前に消します｡同じoutを使い回せば､outのメモリは大きくなるとき以外確保し直しません
+    pub fn speak_into(
+        &mut self,
+        text: &str,
+        speed: i32,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let koe = self.kanji2koe.convert_into(text, &mut self.koe)?;
+        let wav = self.dll.synthe(koe, speed)?;
+        out.clear();
+        out.extend_from_slice(&wav);
+        Ok(())
+    }
+
+    /// 持っているAqKanji2KoeのインスタンスとAquesTalk.dllを取り出します
+    pub fn into_inner(self) -> (AqK2Kinstance<'a>, AqDLL<'a>) {
+        (self.kanji2koe, self.dll)
+    }
+}
+
+/// # TtsEngineが返すエラー
+pub enum EngineError {
+    /// sayを呼びましたが､with_playerで再生する処理が設定されていません
+    NoPlayer,
+}
+
+impl EngineError {
+    fn msg(&self) -> &str {
+        match self {
+            EngineError::NoPlayer => "再生する処理が設定されていない",
+        }
+    }
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::fmt::Debug for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn description(&self) -> &str {
+        self.msg()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock,
+        wav::{self, WavHeader},
+    };
+    use std::{env, process, sync::mpsc, time::Duration};
+
+    fn engine() -> TtsEngine<'static> {
+        let instance = mock::aqkanji2koe().create("dic").unwrap();
+        TtsEngine::new(mock::aquestalk()).with_kanji2koe(instance)
+    }
+
+    #[test]
+    fn say_to_file() {
+        let mut engine = engine();
+        let path = env::temp_dir().join(format!("aquestalk_rs-engine-{}.wav", process::id()));
+        engine.say_to_file("ゆっくり", &path).unwrap();
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // 偽のAqKanji2Koeは入力をそのまま返すので､4文字分の音声になります
+        let header = WavHeader::parse(&wav).unwrap();
+        assert_eq!(header.duration(), mock::CHAR_DURATION * 4);
+    }
+
+    #[test]
+    fn say_with_player() {
+        let mut engine = engine();
+        assert!(matches!(
+            engine
+                .say("あ")
+                .err()
+                .unwrap()
+                .downcast_ref::<EngineError>(),
+            Some(EngineError::NoPlayer)
+        ));
+        let (tx, rx) = mpsc::channel();
+        let mut engine = engine.with_player(move |wav| {
+            tx.send(wav.to_vec())?;
+            Ok(())
+        });
+        engine.say("あい").unwrap();
+        let header = WavHeader::parse(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(header.duration(), mock::CHAR_DURATION * 2);
+    }
+
+    #[test]
+    fn speed() {
+        let mut engine = engine();
+        engine.set_speed(150).unwrap();
+        assert!(engine.set_speed(400).is_err());
+        assert_eq!(engine.speed(), 150);
+    }
+
+    #[test]
+    fn cache() {
+        let mut engine = engine().with_cache(2);
+        mock::take_convert_sizes();
+        engine.speak("あ").unwrap();
+        engine.speak("あ").unwrap();
+        engine.speak("い").unwrap();
+        assert_eq!(mock::take_convert_sizes().len(), 2);
+    }
+
+    #[test]
+    fn health_check() {
+        let mut engine = engine();
+        engine.health_check().unwrap();
+        mock::push_synthe(mock::SyntheResult::Error(100));
+        let e = engine.health_check().err().unwrap();
+        assert_eq!(
+            e.downcast_ref::<Aq1Error>().and_then(Aq1Error::code),
+            Some(100)
+        );
+        // 音声が空の場合
+        let empty = wav::silence_wav(mock::SPEC, Duration::from_secs(0)).unwrap();
+        mock::push_synthe(mock::SyntheResult::Data(empty));
+        let e = engine.health_check().err().unwrap();
+        assert!(matches!(
+            e.downcast_ref::<WavError>(),
+            Some(WavError::TooShort)
+        ));
+        mock::push_convert(mock::ConvertResult::Error(201, Vec::new()));
+        assert!(engine.health_check().is_err());
+    }
+
+    #[test]
+    fn session_reuses_out() {
+        let instance = mock::aqkanji2koe().create("dic").unwrap();
+        let mut session = Session::new(instance, mock::aquestalk());
+        let mut out = Vec::new();
+        session.speak_into("あいう", 100, &mut out).unwrap();
+        assert_eq!(
+            WavHeader::parse(&out).unwrap().duration(),
+            mock::CHAR_DURATION * 3
+        );
+        // 前の中身は消してから書き込みます
+        let capacity = out.capacity();
+        session.speak_into("あ", 100, &mut out).unwrap();
+        assert_eq!(
+            WavHeader::parse(&out).unwrap().duration(),
+            mock::CHAR_DURATION
+        );
+        assert_eq!(out.capacity(), capacity);
+        let (_, dll) = session.into_inner();
+        assert_eq!(dll.active_references(), 0);
+    }
+
+    #[test]
+    fn preprocessor_and_no_kanji2koe() {
+        let mut engine = TtsEngine::new(mock::aquestalk())
+            .with_preprocessor(|text| Cow::Owned(text.replace('!', "")));
+        assert_eq!(engine.to_koe("あ!").unwrap(), "あ");
+    }
+}
@@ -1,58 +1,313 @@
+// loggingフィーチャーが有効な場合だけlogクレートにログを出力するためのマクロ
+#[cfg(feature = "logging")]
+macro_rules! debug_log {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! warn_log {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! warn_log {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+/// # WAVデータを読むためのヘルパー
+/// AquesTalkが生成するWAVデータ(RIFF形式)のヘッダーを解析するのに使います
+pub mod wav;
+
+/// # 音声記号列の解析
+/// AquesTalkに渡す音声記号列を拍(モーラ)ごとに分解し､アクセントやポーズの位置を調べるのに使います
+/// ## Examples
+/// ```
+/// use aquestalk_rs::phonetics::{parse, Mora};
+///
+/// let moras = parse("ゆ'っくり/していってね。").unwrap();
+/// assert_eq!(
+///     moras[0],
+///     Mora::Kana {
+///         kana: "ゆ".to_string(),
+///         accent: true,
+///         devoiced: false
+///     }
+/// );
+/// ```
+pub mod phonetics;
+
+/// # AquesTalkの音声記号の一覧
+/// 音声記号列の検証や解析､入力補完などに使える音声記号の表です
+/// ## Examples
+/// ```
+/// use aquestalk_rs::symbols::is_valid_symbol;
+///
+/// assert!(is_valid_symbol("きゃ"));
+/// assert!(!is_valid_symbol("abc"));
+/// ```
+pub mod symbols;
+
+/// # 音声記号列に埋め込む指定のヘルパー
+/// AqKanji2Koeの変換結果に読みの上書きなどを組み込んだり､音声記号列のタグをAquesTalkに渡す前に確かめたりするのに使います
+pub mod tags;
+
+/// # AqKanji2Koeに渡す前のテキストの下ごしらえ
+/// 日付や桁区切りのある数字､半角カタカナなど､そのままでは読みを間違えやすい表記を書き換えます
+/// ## Examples
+/// ```
+/// use aquestalk_rs::text_normalize::normalize_numbers;
+///
+/// assert_eq!(normalize_numbers("2024/01/02"), "二千二十四年一月二日");
+/// assert_eq!(normalize_numbers("1,234円"), "千二百三十四円");
+/// ```
+pub mod text_normalize;
+
+/// # AquesTalkとAqKanji2Koeをまとめて使うための高水準なラッパー
+/// ## Examples
+/// ```no_run
+/// use aquestalk_rs::{aqkanji2koe::AqK2KDLL, aquestalk1::AqDLL, engine::TtsEngine};
+///
+/// fn main() {
+///     let aqk2k = AqK2KDLL::load("./aqk2k/lib64/AqKanji2Koe.dll", None).unwrap();
+///     let dll = AqDLL::load("./aquestalk/f1/AquesTalk.dll").unwrap();
+///     let mut engine = TtsEngine::new(dll).with_kanji2koe(aqk2k.create("./aqk2k/aq_dic").unwrap());
+///     engine.say_to_file("ゆっくりしていってね", "./reimu.wav").unwrap();
+/// }
+/// ```
+#[allow(clippy::needless_doctest_main)]
+pub mod engine;
+
+/// テストでDLLの代わりに使う偽の関数
+#[cfg(test)]
+mod mock;
+
+/// DLLから関数を探すためのトレイトです
+/// 普段はlibloadingのLibraryを使い､テストではDLLの代わりに偽の関数を返すmock::MockLibを使います
+trait SymbolSource {
+    /// nameの関数のポインタを返します
+    /// # Safety
+    /// Tは関数ポインタの型で､その関数の本当の型と合っていなければなりません
+    unsafe fn symbol<T: Copy>(&self, name: &str) -> Result<T, libloading::Error>;
+
+    /// 関数を探し終わった後で､関数を使い終わるまでDLLを開放しないように持っておくLibraryを返します
+    fn into_library(self) -> Option<libloading::Library>;
+
+    /// 指定された関数をエクスポートしているかを調べます｡読み込むDLLを取り違えていないか確かめるのに使います
+    fn has_symbol(&self, name: &str) -> bool {
+        unsafe { self.symbol::<unsafe extern "C" fn()>(name).is_ok() }
+    }
+}
+
+impl SymbolSource for libloading::Library {
+    unsafe fn symbol<T: Copy>(&self, name: &str) -> Result<T, libloading::Error> {
+        // 関数ポインタをコピーして取り出します｡ポインタはこのLibraryが開放されるまで有効です
+        Ok(*self.get::<T>(name.as_bytes())?)
+    }
+
+    fn into_library(self) -> Option<libloading::Library> {
+        Some(self)
+    }
+}
+
 /// # AquesTalk1のラッパー
 /// 基本的な流れとしてはAquesTalk.dllを読み込む→音声データを生成するというように使います
 /// ## Examples
-/// ```
-/// use testing::aquestalk1::AqDLL;
+/// ```no_run
+/// use aquestalk_rs::aquestalk1::AqDLL;
 /// use std::{fs::File, io::Write};
 ///
 /// fn main() {
 ///     let reimu = AqDLL::load("./aquestalk/f1/AquesTalk.dll").unwrap();
 ///     let reimuvoice = reimu.synthe("ゆっくりしていってね", 100).unwrap();
 ///     let mut file = File::create("./reimu.wav").unwrap();
-///     file.write_all(*reimuvoice).unwrap();
+///     file.write_all(&reimuvoice).unwrap();
 /// }
 /// ```
 #[allow(clippy::needless_doctest_main)]
 pub mod aquestalk1 {
-    use libloading::{Library, Symbol};
+    use crate::{
+        phonetics::{self, MoraTiming},
+        symbols,
+        wav::{self, WavError, WavHeader},
+        SymbolSource,
+    };
+    use libloading::Library;
     use safety_breaker::{force_convert, ForceMut};
     use std::{
         convert::TryFrom,
+        env,
         ffi::{CString, OsStr},
-        mem::MaybeUninit,
+        fs,
+        io::{Cursor, Seek, SeekFrom, Write},
+        marker::PhantomData,
+        mem,
         os::raw::c_char,
-        sync::Arc,
+        path::{Path, PathBuf},
+        process, ptr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
     };
-    type AqSynthe<'a> = Symbol<'a, unsafe extern "C" fn(*const c_char, i32, *mut i32) -> *mut u8>;
-    type AqFreeWav<'a> = Symbol<'a, unsafe extern "C" fn(*mut u8)>;
+    // DLLの関数は__stdcallで公開されているので､extern "system"として宣言しています
+    // extern "system"は32bit版のWindowsでは__stdcallに､それ以外(64bit版のWindowsやLinux)ではextern "C"と同じになります
+    // extern "system"で宣言した外部の関数からunwindが起きるのは未定義動作です｡AquesTalkはCのライブラリで例外を投げないので､このままにしています
+    type AqSynthe = unsafe extern "system" fn(*const c_char, i32, *mut i32) -> *mut u8;
+    type AqFreeWav = unsafe extern "system" fn(*mut u8);
 
     /// DLL内の関数にアクセスするためのラッパー
+    ///
+    /// DLL(Library)は参照カウント付きで持っていて､AqDLLとそこから作られたAqWAVが全てDropされるまで開放されません
+    /// そのため､AqDLLやAqWAVを持っている間にDLLが開放されて関数の呼び出しが失敗することはありません
+    /// 同じパスのDLLをもう一度loadしても別のAqDLLになり､古いAqDLLは古いDLLを持ち続けます(DLLを入れ替えるときは､古いAqDLLとAqWAVを全てDropしてからloadし直してください)
+    /// 例外はsynthe_leakで取り出したポインタと､leakで作ったAqStaticDLLです｡前者はAqDLLより先に開放する必要があり､後者はDLLを開放しません
+    ///
+    /// cloneしたAqDLLは読み込んだDLLを共有し､DLLを読み込み直すことはありません｡発話速度などの設定はclone時にコピーされ､その後はそれぞれ別々に変更できます
+    /// AqDLLはSendかつSyncなので､cloneしたものを別々のスレッドに渡してそれぞれで音声を合成できます
+    #[derive(Clone)]
     pub struct AqDLL<'a> {
         dll: Arc<AqDLL2<'a>>,
+        default_speed: i32,
+        speed_policy: SpeedPolicy,
+        check_wav: bool,
+        max_koe_len: Option<usize>,
+    }
+
+    /// # 発話速度が50-300の範囲外だったときの扱い
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub enum SpeedPolicy {
+        /// Aq1Error::InvalidSpeedを返します(初期設定)
+        #[default]
+        Error,
+        /// 50-300の範囲に丸めて合成します｡UIのスライダーなどで範囲を少しはみ出す場合向けです
+        Clamp,
+    }
+
+    impl SpeedPolicy {
+        /// 発話速度ispeedにこの扱いを当てはめて､DLLに渡す発話速度を返します
+        fn apply(self, ispeed: i32) -> Result<i32, Aq1Error> {
+            match self {
+                SpeedPolicy::Clamp => Ok(ispeed.clamp(50, 300)),
+                SpeedPolicy::Error if !(50..=300).contains(&ispeed) => {
+                    Err(Aq1Error::InvalidSpeed(ispeed))
+                }
+                SpeedPolicy::Error => Ok(ispeed),
+            }
+        }
     }
 
     struct AqDLL2<'a> {
-        lib: Library,
-        synthe: AqSynthe<'a>,
-        freewav: AqFreeWav<'a>,
+        // 関数ポインタはlibが開放されるまで有効なので､AqDLL2と一緒に持っておきます｡テストで偽の関数を使う場合はNoneです
+        #[allow(dead_code)]
+        lib: Option<Library>,
+        // UTF-8版のAquesTalk_Synthe_Utf8がないDLLもあるので､読み込めなかった場合はNoneにします
+        synthe: Option<AqSynthe>,
+        // Shift_JIS版のAquesTalk_Syntheがあるかどうか(このクレートからは呼びません)
+        sjis: bool,
+        freewav: AqFreeWav,
+        // フィールドは宣言順にDropされるので､libが開放されてから一時ファイルが削除されます
+        tmp: Option<TempDLL>,
+        // 公開している型の寿命引数に合わせるためのものです
+        _lib: PhantomData<&'a Library>,
+    }
+
+    /// load_from_bytesで書き出した一時ファイル､Drop時に削除します
+    struct TempDLL(PathBuf);
+
+    impl std::ops::Drop for TempDLL {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
     }
 
+    static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
     impl<'a> AqDLL<'a> {
         /// AquesTalk.dllを読み込むための関数です｡引数にはAquesTalk.dllのパスを指定してください
-        #[allow(clippy::uninit_assumed_init)]
+        /// パスはOSのネイティブな形式のまま(WindowsではUTF-16でLoadLibraryExWに)渡されるので､日本語を含むディレクトリに置かれたDLLでも読み込めます
         pub fn load<P: AsRef<OsStr>>(dllpath: P) -> Result<Self, Box<dyn std::error::Error>> {
+            let dllpath = dllpath.as_ref();
+            debug_log!("AquesTalk.dllを読み込みます: {:?}", dllpath);
+            unsafe { Ok(Self::from_source(Library::new(dllpath)?)?) }
+        }
+
+        /// sourceから関数を探してAqDLLを作ります｡loadの本体で､テストではDLLの代わりに偽の関数を渡すのに使います
+        /// # Safety
+        /// sourceの関数は､AquesTalkの関数と同じ型でなければなりません
+        pub(crate) unsafe fn from_source<S: SymbolSource>(source: S) -> Result<Self, Aq1Error> {
+            if !source.has_symbol("AquesTalk_FreeWave") && source.has_symbol("AqKanji2Koe_Create") {
+                return Err(Aq1Error::WrongDll);
+            }
+            let synthe = source.symbol::<AqSynthe>("AquesTalk_Synthe_Utf8").ok();
+            let sjis = source.has_symbol("AquesTalk_Synthe");
+            if synthe.is_none() && !sjis {
+                // どちらもなければAquesTalkのDLLではないので､UTF-8版がないことをエラーにします
+                symbol::<AqSynthe, S>(&source, "AquesTalk_Synthe_Utf8")?;
+            }
+            let freewav = symbol(&source, "AquesTalk_FreeWave")?;
+            Ok(AqDLL {
+                dll: Arc::new(AqDLL2 {
+                    lib: source.into_library(),
+                    synthe,
+                    sjis,
+                    freewav,
+                    tmp: None,
+                    _lib: PhantomData,
+                }),
+                default_speed: 100,
+                speed_policy: SpeedPolicy::default(),
+                check_wav: false,
+                max_koe_len: None,
+            })
+        }
+
+        /// メモリ上にあるAquesTalk.dllの中身を読み込むための関数です｡include_bytes!で埋め込んだDLLなどを渡してください
+        /// OSのローダーはメモリから直接DLLを読み込めないため､一時ディレクトリにファイルを書き出してから読み込みます
+        /// 一時ファイルはDLLが開放されるとき(AqDLLと全てのAqWAVがDropされたとき)に削除されますが､プロセスが異常終了した場合は残ることがあります
+        pub fn load_from_bytes(dll: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+            let path = env::temp_dir().join(format!(
+                "aquestalk_rs-{}-{}{}",
+                process::id(),
+                TEMP_COUNT.fetch_add(1, Ordering::Relaxed),
+                env::consts::DLL_SUFFIX
+            ));
+            // create_newで既存のファイル(シンボリックリンクを含む)を上書きしないようにしています
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            let tmp = TempDLL(path);
+            file.write_all(dll)?;
+            drop(file);
+            let aqdll = Self::load(&tmp.0)?;
             unsafe {
-                let dll = AqDLL {
-                    dll: Arc::new(AqDLL2 {
-                        lib: Library::new(dllpath)?,
-                        synthe: MaybeUninit::uninit().assume_init(),
-                        freewav: MaybeUninit::uninit().assume_init(),
-                    }),
-                };
-                *dll.dll.synthe.forcemut() = dll.dll.lib.get(b"AquesTalk_Synthe_Utf8")?;
-                *dll.dll.freewav.forcemut() = dll.dll.lib.get(b"AquesTalk_FreeWave")?;
-                Ok(dll)
+                *aqdll.dll.tmp.forcemut() = Some(tmp);
             }
+            Ok(aqdll)
+        }
+
+        /// 声の種類ごとのディレクトリに置かれたAquesTalk.dllを読み込みます
+        /// 第一引数には声ごとのディレクトリが入っているディレクトリを指定してください(例えばWindowsで"./aquestalk"を指定してVoice::F1を選ぶと"./aquestalk/f1/AquesTalk.dll"を読み込みます)
+        pub fn load_voice<P: AsRef<Path>>(
+            base_dir: P,
+            voice: Voice,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            Self::load(voice.path(base_dir))
         }
 
         /// AquesTalk_Synthe_Utf8と同じです｡第一引数は音声記号列､第二引数は発話速度を50-300で指定します
@@ -61,183 +316,1542 @@ pub mod aquestalk1 {
             koe: &str,
             ispeed: i32,
         ) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+            self.wrap(
+                self.dll
+                    .synthe(koe, self.check_speed(ispeed)?, self.max_koe_len)?,
+            )
+        }
+
+        /// syntheと同じですが､音声記号列をバイト列で受け取り､UTF-8として検証せずにそのままDLLに渡します
+        /// 他のツールがバイト列で保存した音声記号列を使うとき向けです｡途中にNUL文字を含む場合はエラーを返します
+        pub fn synthe_bytes<'b>(
+            &self,
+            koe: &[u8],
+            ispeed: i32,
+        ) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+            self.wrap(
+                self.dll
+                    .synthe(koe, self.check_speed(ispeed)?, self.max_koe_len)?,
+            )
+        }
+
+        fn wrap<'b>(&self, wav: &'b mut [u8]) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+            let wav = unsafe {
+                AqWAV {
+                    wav,
+                    // dll: Arc::clone(&*(&self.dll as *const _ as *mut Arc<AqDLL2>)),
+                    dll: AqDLLRef::Shared(Arc::clone(force_convert!(&self.dll, Arc<AqDLL2>))),
+                }
+            };
+            check_wav(self.check_wav, wav)
+        }
+
+        /// Someにすると､syntheなどはmaxバイト(UTF-8)より長い音声記号列をDLLに渡さずにAq1Error::InputTooLongを返すようになります
+        /// DLLが受け付ける長さの上限は公開されていないので初期設定はNone(確かめない)です｡使っている声のDLLで上限を確かめてある場合に設定してください
+        /// synthe_long_to_fileは､音声記号列をMAX_KOE_LENとmaxの短い方で分けます
+        pub fn set_max_koe_len(&mut self, max: Option<usize>) {
+            self.max_koe_len = max;
+        }
+
+        /// set_max_koe_lenで設定した音声記号列の長さの上限を返します
+        pub fn max_koe_len(&self) -> Option<usize> {
+            self.max_koe_len
+        }
+
+        /// trueにすると､synthe関数がDLLから受け取ったWAVデータのヘッダーを検証し､RIFF形式でなかったりfmtチャンクやdataチャンクが見つからなかったりした場合はWavErrorを返すようになります
+        /// 呼び出し規約の違うDLLを読み込んだ場合など､DLLが壊れたデータを返したときに気付きやすくなります｡初期設定ではfalseです
+        pub fn set_check_wav(&mut self, check: bool) {
+            self.check_wav = check;
+        }
+
+        /// syntheと同じですが､DLLが返したWAVデータのポインタと長さ(バイト単位)をAqWAVで包まずにそのまま返します
+        /// Rust側でバッファーを持たずにCのコードへ渡したい場合向けです｡set_check_wavでtrueにしていればsyntheと同じく検証し､不正なデータは開放してエラーを返します
+        /// 返されたポインタは自動では開放されないので､使い終わったら必ず同じAqDLL(またはそのクローン)のfree_wavで開放するか､AqWAV::from_rawで包み直してください
+        /// AqWAVと違ってDLLの参照カウントを持たないので､開放するまでAqDLLとそのクローンを全てDropしないでください
+        pub fn synthe_leak(
+            &self,
+            koe: &str,
+            ispeed: i32,
+        ) -> Result<(*mut u8, usize), Box<dyn std::error::Error>> {
+            let wav = self.wrap(self.dll.synthe(
+                koe,
+                self.check_speed(ispeed)?,
+                self.max_koe_len,
+            )?)?;
+            Ok(wav.into_raw())
+        }
+
+        /// synthe_leakが返したポインタをAquesTalk_FreeWaveで開放します
+        /// # Safety
+        /// wavはこのDLLのsynthe_leakが返したポインタで､まだ開放していないものでなければなりません｡開放した後はwavを使わないでください
+        pub unsafe fn free_wav(&self, wav: *mut u8) {
+            if !wav.is_null() {
+                (self.dll.freewav)(wav);
+            }
+        }
+
+        /// DLLがUTF-8版の音声合成関数(AquesTalk_Synthe_Utf8)を持っているかどうかを返します
+        /// falseの場合､syntheなどはAq1Error::SymbolUnavailableを返します
+        pub fn supports_utf8(&self) -> bool {
+            self.dll.synthe.is_some()
+        }
+
+        /// DLLがShift_JIS版の音声合成関数(AquesTalk_Synthe)を持っているかどうかを返します
+        /// このクレートはShift_JIS版を呼ばないので､Shift_JISで合成したい場合は自分で呼んでください
+        pub fn supports_sjis(&self) -> bool {
+            self.dll.sjis
+        }
+
+        /// 発話速度が50-300の範囲外だったときに､エラーにするか範囲内に丸めるかを設定します
+        pub fn set_speed_policy(&mut self, policy: SpeedPolicy) {
+            self.speed_policy = policy;
+        }
+
+        /// 発話速度が範囲外だったときの扱いを返します
+        pub fn speed_policy(&self) -> SpeedPolicy {
+            self.speed_policy
+        }
+
+        fn check_speed(&self, ispeed: i32) -> Result<i32, Aq1Error> {
+            self.speed_policy.apply(ispeed)
+        }
+
+        /// このAqDLL以外に読み込んだDLLを参照しているもの(Dropされていないクローンや､syntheで作ったAqWAV)の数を返します
+        /// 0ならこのAqDLLをDropした時点でDLLが開放されるので､DLLを入れ替える前にAqWAVの開放し忘れがないか確かめるのに使えます
+        /// synthe_leakやinto_rawで取り出したポインタは参照を持たないので数に入りません
+        pub fn active_references(&self) -> usize {
+            Arc::strong_count(&self.dll) - 1
+        }
+
+        /// synthe_defaultで使う発話速度を50-300で設定します｡範囲外の値を指定した場合はエラーを返し､設定は変わりません
+        pub fn set_default_speed(&mut self, ispeed: i32) -> Result<(), Aq1Error> {
+            if !(50..=300).contains(&ispeed) {
+                return Err(Aq1Error::InvalidSpeed(ispeed));
+            }
+            self.default_speed = ispeed;
+            Ok(())
+        }
+
+        /// synthe_defaultで使う発話速度を返します｡初期値は100です
+        pub fn default_speed(&self) -> i32 {
+            self.default_speed
+        }
+
+        /// set_default_speedで設定した発話速度で音声を合成します
+        pub fn synthe_default<'b>(
+            &self,
+            koe: &str,
+        ) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+            self.synthe(koe, self.default_speed)
+        }
+
+        /// syntheと同じように音声を合成し､音声記号列の拍ごとの時刻を推定して一緒に返します
+        /// 時刻はphonetics::estimate_timingsで音声の長さを拍に割り振った推定値で､字幕や口パクの目安に使えます
+        pub fn synthe_timed<'b>(
+            &self,
+            koe: &str,
+            ispeed: i32,
+        ) -> Result<(AqWAV<'b>, Vec<MoraTiming>), Box<dyn std::error::Error>> {
+            let moras = phonetics::parse(koe)?;
+            let wav = self.synthe(koe, ispeed)?;
+            let timings = phonetics::estimate_timings(&moras, wav.duration()?);
+            Ok((wav, timings))
+        }
+
+        /// 音声記号列を拍ごとに分けて1つずつ合成し､拍の音声記号と音声の組を順に返します
+        /// synthe_timedの推定と違って実際に合成した音声なので､キャラクターのアニメーションのキーフレームなどに使えます｡ただし拍の数だけ合成するので重くなります
+        /// 分け方は次のとおりです
+        /// - 読み記号1拍(拗音は2文字で1拍)ごとに分けます｡無声化した拍は_を付けたまま合成します
+        /// - っとーは単独では発音できないので､直前の拍に繋げます
+        /// - アクセント記号(')は1拍だけでは意味がないので取り除き､ポーズや区切り､タグは合成しません
+        ///
+        /// 拍ごとに合成するので､全体を一度に合成した音声とは抑揚や長さが少し違います
+        pub fn synthe_phonemes(
+            &self,
+            koe: &str,
+            ispeed: i32,
+        ) -> Result<Vec<(String, wav::OwnedWav)>, Box<dyn std::error::Error>> {
+            let mut units: Vec<String> = Vec::new();
+            for mora in phonetics::parse(koe)? {
+                if let phonetics::Mora::Kana { kana, devoiced, .. } = mora {
+                    match units.last_mut() {
+                        Some(last) if kana == "っ" || kana == "ー" => last.push_str(&kana),
+                        _ if devoiced => units.push(format!("_{}", kana)),
+                        _ => units.push(kana),
+                    }
+                }
+            }
+            units
+                .into_iter()
+                .map(|unit| {
+                    let wav = self.synthe(&unit, ispeed)?.to_owned_wav()?;
+                    Ok((unit, wav))
+                })
+                .collect()
+        }
+
+        /// 長い音声記号列を文ごとに分けて少しずつ合成し､1つのWAVファイルとしてpathに書き出します
+        /// 全体をメモリに持たないので､本1冊分のような長い音声記号列でもメモリをあまり使いません
+        /// 記号列は。や？の後で分け､それでも長すぎる文は、や/の後､最後は拍の区切りで分けます
+        /// 途中でエラーが起きた場合は､書きかけのファイルを削除してからエラーを返します
+        pub fn synthe_long_to_file<P: AsRef<Path>>(
+            &self,
+            koe: &str,
+            ispeed: i32,
+            path: P,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let path = path.as_ref();
+            let mut file = fs::File::create(path)?;
+            let result = self.write_long(&mut file, koe, ispeed);
+            drop(file);
+            if result.is_err() {
+                let _ = fs::remove_file(path);
+            }
+            result
+        }
+
+        fn write_long(
+            &self,
+            file: &mut fs::File,
+            koe: &str,
+            ispeed: i32,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let mut header: Option<WavHeader> = None;
+            let mut data_len: u32 = 0;
+            let max = self
+                .max_koe_len
+                .map_or(MAX_KOE_LEN, |max| max.min(MAX_KOE_LEN));
+            for chunk in split_koe(koe, max) {
+                let wav = self.synthe(chunk, ispeed)?;
+                let header2 = WavHeader::parse(&wav)?;
+                match &header {
+                    Some(h) => {
+                        if (h.format_tag, h.channels, h.sample_rate, h.bits_per_sample)
+                            != (
+                                header2.format_tag,
+                                header2.channels,
+                                header2.sample_rate,
+                                header2.bits_per_sample,
+                            )
+                        {
+                            return Err(Box::new(WavError::UnsupportedFormat));
+                        }
+                    }
+                    None => {
+                        file.write_all(&wav::header_bytes(&header2, 0))?;
+                        header = Some(header2);
+                    }
+                }
+                let data = &wav[header2.data_offset..header2.data_offset + header2.data_len];
+                data_len = data_len
+                    .checked_add(TryFrom::try_from(data.len())?)
+                    .filter(|l| *l <= u32::MAX - 36)
+                    .ok_or(WavError::TooLong)?;
+                file.write_all(data)?;
+            }
+            let header = header.ok_or(Aq1Error::Code(111))?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&wav::header_bytes(&header, data_len))?;
+            file.flush()?;
+            Ok(())
+        }
+
+        /// syntheがエラーコード109か110(サウンドドライバ関連のエラー)を返したときに､待ち時間を10ミリ秒から倍々に増やしながら最大attempts回まで試行します
+        /// それ以外のエラーはすぐに返し､attempts回失敗した場合は最後のエラーを返します
+        pub fn synthe_retry<'b>(
+            &self,
+            koe: &str,
+            ispeed: i32,
+            attempts: u32,
+        ) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+            let mut wait = Duration::from_millis(10);
+            let mut count = 1;
+            loop {
+                match self.synthe(koe, ispeed) {
+                    Err(e)
+                        if count < attempts
+                            && matches!(
+                                e.downcast_ref::<Aq1Error>(),
+                                Some(Aq1Error::Code(109)) | Some(Aq1Error::Code(110))
+                            ) =>
+                    {
+                        thread::sleep(wait);
+                        wait *= 2;
+                        count += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+
+    impl AqDLL<'static> {
+        /// DLLをプログラムの終了まで開放しないようにして､AqStaticDLLに変換します
+        /// AqStaticDLLのsynthe関数は参照カウントの操作をしないので少しだけ速くなりますが､DLLを開放する手段はなくなります
+        /// サーバーのように､起動時に一度だけDLLを読み込んでずっと使い続けるプログラム向けです
+        /// 発話速度やSpeedPolicy､set_check_wavとset_max_koe_lenの設定はAqStaticDLLにそのまま引き継がれます
+        pub fn leak(self) -> AqStaticDLL {
+            AqStaticDLL {
+                dll: Box::leak(Box::new(self.dll)),
+                default_speed: self.default_speed,
+                speed_policy: self.speed_policy,
+                check_wav: self.check_wav,
+                max_koe_len: self.max_koe_len,
+            }
+        }
+    }
+
+    /// checkがtrueならwavのヘッダーを検証し､不正ならエラーを返します
+    fn check_wav<'b>(check: bool, wav: AqWAV<'b>) -> Result<AqWAV<'b>, Box<dyn std::error::Error>> {
+        if check {
+            // 不正なデータだった場合もwavのDropで開放されます
+            WavHeader::parse(wav.wav)?;
+        }
+        Ok(wav)
+    }
+
+    /// DLLが返すWAVデータの長さとして受け付ける上限(バイト単位)
+    /// 8kHz､16bitのモノラルで4時間半を超える長さに当たり､これを超える長さはDLLの不具合とみなします
+    const MAX_WAV_LEN: usize = 256 * 1024 * 1024;
+
+    /// synthe_long_to_fileが音声記号列を分けるときの､1回分の長さの目安(UTF-8でのバイト単位)
+    /// DLLが受け付ける長さの上限は公開されていないので､これはDLLの上限ではなく､エラーコード200や204(内部バッファオーバー)を避けるためにこのクレートが選んだ値です
+    /// syntheはset_max_koe_lenで上限を設定しない限りこの長さを確かめずにDLLに渡すので､長すぎる場合はDLLが返すエラーコードがそのままAq1Error::Codeになります
+    pub const MAX_KOE_LEN: usize = 1024;
+
+    /// 音声記号列をmaxバイト以下の部分に分けます
+    fn split_koe(koe: &str, max: usize) -> Vec<&str> {
+        let mut result = Vec::new();
+        let mut rest = koe;
+        while rest.len() > max {
+            let cut = split_point(rest, max);
+            result.push(&rest[..cut]);
+            rest = &rest[cut..];
+        }
+        if !rest.is_empty() {
+            result.push(rest);
+        }
+        result
+    }
+
+    /// rest[..cut]がmaxバイト以下になるような区切り位置を探します
+    /// 。や？の後を優先し､次に、や/の後､どちらもなければ拍の区切りで分けます｡<NUM VAL=3.14>のようなタグの中では切りません
+    fn split_point(rest: &str, max: usize) -> usize {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let head = &rest[..end];
+        let tiers: [fn(char) -> bool; 2] = [
+            |c| symbols::SENTENCE_ENDS.contains(&c),
+            |c| c == '/' || symbols::SHORT_PAUSES.contains(&c),
+        ];
+        for is_sep in &tiers {
+            if let Some((i, c)) = head
+                .char_indices()
+                .rev()
+                .find(|(i, c)| is_sep(*c) && tag_start(rest, *i).is_none())
+            {
+                return i + c.len_utf8();
+            }
+        }
+        // タグの中や､無声化記号の後､拗音の小さい文字とアクセント記号､ポーズの前では切らないようにします
+        let mut cut = end;
+        loop {
+            if let Some(start) = tag_start(rest, cut) {
+                cut = start;
+            }
+            let prev = match rest[..cut].chars().next_back() {
+                Some(prev) => prev,
+                None => break,
+            };
+            let next = rest[cut..].chars().next().unwrap_or(' ');
+            // 拗音のように2文字で1つの読み記号の間でも切りません
+            let in_reading = symbols::reading_at(&rest[cut - prev.len_utf8()..])
+                .is_some_and(|r| r.len() > prev.len_utf8());
+            if prev != '_' && !in_reading && next != '\'' && next != '/' && !symbols::is_pause(next)
+            {
+                break;
+            }
+            cut -= prev.len_utf8();
+        }
+        if cut > 0 {
+            cut
+        } else if let Some(start) = tag_start(rest, end) {
+            // 先頭のタグだけでmaxを超える場合は､タグを分けずにタグの終わりで切ります
+            rest[start..]
+                .find('>')
+                .map_or(rest.len(), |i| start + i + 1)
+        } else if end > 0 {
+            end
+        } else {
+            rest.chars().next().map_or(rest.len(), char::len_utf8)
+        }
+    }
+
+    /// koe[..pos]の中に閉じられていないタグがあれば､つまりposがタグの中なら､タグの開始位置を返します
+    fn tag_start(koe: &str, pos: usize) -> Option<usize> {
+        let start = koe[..pos].rfind('<')?;
+        if koe[start..pos].contains('>') {
+            None
+        } else {
+            Some(start)
+        }
+    }
+
+    unsafe fn symbol<T: Copy, S: SymbolSource>(lib: &S, name: &'static str) -> Result<T, Aq1Error> {
+        lib.symbol(name).map_err(|source| Aq1Error::MissingSymbol {
+            symbol: name,
+            source,
+        })
+    }
+
+    impl<'a> AqDLL2<'a> {
+        /// max_koe_lenがSomeなら､それより長い音声記号列はDLLに渡さずにエラーにします
+        fn synthe<'b, K: Into<Vec<u8>>>(
+            &self,
+            koe: K,
+            ispeed: i32,
+            max_koe_len: Option<usize>,
+        ) -> Result<&'b mut [u8], Box<dyn std::error::Error>> {
             unsafe {
                 let koe2 = CString::new(koe)?;
+                let len = koe2.as_bytes().len();
+                if let Some(max) = max_koe_len.filter(|max| len > *max) {
+                    return Err(Box::new(Aq1Error::InputTooLong { len, max }));
+                }
                 let mut size = 0;
-                let wav = (self.dll.synthe)(koe2.as_ptr(), ispeed, &mut size as *mut i32);
+                let start = Instant::now();
+                let synthe = self
+                    .synthe
+                    .ok_or(Aq1Error::SymbolUnavailable("AquesTalk_Synthe_Utf8"))?;
+                let wav = synthe(koe2.as_ptr(), ispeed, &mut size as *mut i32);
+                debug_log!("AquesTalk_Synthe_Utf8: {:?}", start.elapsed());
                 if wav.is_null() {
-                    Err(Box::new(AqErr(size)))
+                    warn_log!("AquesTalk_Synthe_Utf8がエラーを返しました: {}", size);
+                    Err(Box::new(Aq1Error::Code(size)))
                 } else {
-                    Ok(AqWAV {
-                        wav: std::slice::from_raw_parts_mut(wav, TryFrom::try_from(size)?),
-                        // dll: Arc::clone(&*(&self.dll as *const _ as *mut Arc<AqDLL2>)),
-                        dll: Arc::clone(force_convert!(&self.dll, Arc<AqDLL2>)),
-                    })
+                    match usize::try_from(size) {
+                        Ok(len) if len > 0 && len <= MAX_WAV_LEN => {
+                            Ok(std::slice::from_raw_parts_mut(wav, len))
+                        }
+                        _ => {
+                            // 壊れた長さでスライスを作らずに､バッファーを開放してエラーにします
+                            warn_log!("AquesTalk_Synthe_Utf8が不正な長さを返しました: {}", size);
+                            (self.freewav)(wav);
+                            Err(Box::new(Aq1Error::InvalidWavSize(size)))
+                        }
+                    }
                 }
             }
         }
     }
 
+    /// # AqDLL::leakで作られる､開放されないDLLへのハンドル
+    /// Copyできるので､スレッド間でもそのまま渡せます
+    /// 発話速度などの設定はleakしたときのAqDLLのものを使います
+    #[derive(Clone, Copy)]
+    pub struct AqStaticDLL {
+        dll: &'static AqDLL2<'static>,
+        default_speed: i32,
+        speed_policy: SpeedPolicy,
+        check_wav: bool,
+        max_koe_len: Option<usize>,
+    }
+
+    impl AqStaticDLL {
+        /// AqDLLのsyntheと同じですが､返されるAqWAVはDLLの参照カウントを持ちません
+        /// 発話速度の扱いと音声記号列の長さの上限､WAVデータの検証は､leakしたときのAqDLLの設定に従います
+        pub fn synthe(
+            &self,
+            koe: &str,
+            ispeed: i32,
+        ) -> Result<AqWAV<'static>, Box<dyn std::error::Error>> {
+            let wav = AqWAV {
+                wav: self
+                    .dll
+                    .synthe(koe, self.speed_policy.apply(ispeed)?, self.max_koe_len)?,
+                dll: AqDLLRef::Leaked(self.dll),
+            };
+            check_wav(self.check_wav, wav)
+        }
+
+        /// leakしたときのAqDLLのdefault_speedで音声を合成します
+        pub fn synthe_default(
+            &self,
+            koe: &str,
+        ) -> Result<AqWAV<'static>, Box<dyn std::error::Error>> {
+            self.synthe(koe, self.default_speed)
+        }
+    }
+
+    /// # AquesTalk1に付属している声の一覧
+    /// 配布物では声ごとにf1やm1といったディレクトリにAquesTalk.dllが入っています
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum Voice {
+        /// f1(女声1)
+        F1,
+        /// f2(女声2)
+        F2,
+        /// f3(女声3)
+        F3,
+        /// m1(男声1)
+        M1,
+        /// m2(男声2)
+        M2,
+        /// r1(ロボット)
+        R1,
+        /// dvd
+        Dvd,
+        /// jgr
+        Jgr,
+        /// imd1
+        Imd1,
+    }
+
+    impl Voice {
+        /// 全ての声の一覧です
+        pub const ALL: [Voice; 9] = [
+            Voice::F1,
+            Voice::F2,
+            Voice::F3,
+            Voice::M1,
+            Voice::M2,
+            Voice::R1,
+            Voice::Dvd,
+            Voice::Jgr,
+            Voice::Imd1,
+        ];
+
+        /// 配布物でのディレクトリ名を返します
+        pub fn dir_name(self) -> &'static str {
+            match self {
+                Voice::F1 => "f1",
+                Voice::F2 => "f2",
+                Voice::F3 => "f3",
+                Voice::M1 => "m1",
+                Voice::M2 => "m2",
+                Voice::R1 => "r1",
+                Voice::Dvd => "dvd",
+                Voice::Jgr => "jgr",
+                Voice::Imd1 => "imd1",
+            }
+        }
+
+        /// base_dirの下にあるこの声のライブラリのパスを返します｡ファイル名はdefault_library_nameで決まります
+        pub fn path<P: AsRef<Path>>(self, base_dir: P) -> PathBuf {
+            base_dir
+                .as_ref()
+                .join(self.dir_name())
+                .join(default_library_name())
+        }
+    }
+
+    /// # discover_voicesで見つかった声
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct VoiceEntry {
+        /// ディレクトリ名です
+        pub name: String,
+        /// ライブラリのパスです｡AqDLL::loadにそのまま渡せます
+        pub path: PathBuf,
+        /// 付属の声のディレクトリ名と同じならその声です
+        pub voice: Option<Voice>,
+    }
+
+    /// base_dirの直下にあるディレクトリのうち､default_library_nameのファイルを含むものを声として一覧にします
+    /// 声を選ぶ画面を作るときなどに使えます｡一覧はディレクトリ名の順に並べます
+    pub fn discover_voices<P: AsRef<Path>>(base_dir: P) -> std::io::Result<Vec<VoiceEntry>> {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(base_dir)? {
+            let entry = entry?;
+            let path = entry.path().join(default_library_name());
+            if !entry.file_type()?.is_dir() || !path.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            result.push(VoiceEntry {
+                voice: Voice::ALL.iter().copied().find(|v| v.dir_name() == name),
+                name,
+                path,
+            });
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    /// 今のプラットフォームでの､配布物のライブラリのファイル名を返します
+    /// WindowsではAquesTalk.dll､macOSではlibAquesTalk.dylib､それ以外(Linuxなど)ではlibAquesTalk.soです
+    pub fn default_library_name() -> &'static str {
+        if cfg!(target_os = "windows") {
+            "AquesTalk.dll"
+        } else if cfg!(target_os = "macos") {
+            "libAquesTalk.dylib"
+        } else {
+            "libAquesTalk.so"
+        }
+    }
+
     /// # synthe関数で生成されたwavデータへのスマートポインタ
     /// このスマートポインタを参照外しするとWAVデータのスライスが出てきます
     /// AquesTalk_FreeWaveはDrop時に実行されるため､自分で実行する必要はありません
     pub struct AqWAV<'a> {
         wav: &'a mut [u8],
-        dll: Arc<AqDLL2<'a>>,
+        dll: AqDLLRef<'a>,
+    }
+
+    /// AqWAVが開放されるまでDLLが開放されないようにするための参照
+    enum AqDLLRef<'a> {
+        Shared(Arc<AqDLL2<'a>>),
+        Leaked(&'static AqDLL2<'static>),
+    }
+
+    impl<'a> std::ops::Deref for AqDLLRef<'a> {
+        type Target = AqDLL2<'a>;
+
+        fn deref(&self) -> &Self::Target {
+            match self {
+                AqDLLRef::Shared(dll) => dll,
+                AqDLLRef::Leaked(dll) => dll,
+            }
+        }
+    }
+
+    impl<'a> AqWAV<'a> {
+        /// WAVデータのポインタと長さ(バイト単位)を取り出し､Drop時にAquesTalk_FreeWaveを呼ばないようにします
+        /// Cのコードにバッファーの所有権を渡したい場合向けです｡取り出したポインタはsynthe_leakが返すものと同じ扱いで､使い終わったらAqDLLのfree_wavで開放するか､from_rawで包み直してください
+        /// DLLの参照カウントはここで手放すので､開放するまでAqDLLとそのクローンを全てDropしないでください
+        pub fn into_raw(self) -> (*mut u8, usize) {
+            let mut wav = mem::ManuallyDrop::new(self);
+            let raw = (wav.wav.as_mut_ptr(), wav.wav.len());
+            // WAVデータは開放せず､DLLへの参照だけを手放します
+            unsafe { ptr::drop_in_place(&mut wav.dll) };
+            raw
+        }
+
+        /// into_rawやsynthe_leakで取り出したポインタと長さを､もう一度AqWAVで包みます｡Drop時には今までどおり開放されます
+        /// # Safety
+        /// wavはdll(またはそのクローン)のsynthe_leakかinto_rawが返したnullでないポインタで､まだ開放していないものでなければなりません｡lenもそのとき返された長さにしてください
+        pub unsafe fn from_raw(dll: &AqDLL<'a>, wav: *mut u8, len: usize) -> Self {
+            AqWAV {
+                wav: std::slice::from_raw_parts_mut(wav, len),
+                dll: AqDLLRef::Shared(Arc::clone(&dll.dll)),
+            }
+        }
+
+        /// dataチャンクの中身(PCMデータ)が始まる位置をバイト単位で返します
+        /// 複数のWAVデータを繋げるときなどに､&wav[offset..]でヘッダーを除いた部分を取り出せます
+        pub fn data_offset(&self) -> Result<usize, WavError> {
+            Ok(WavHeader::parse(self.wav)?.data_offset)
+        }
+
+        /// WAVデータ全体をバイト列として返します
+        pub fn as_bytes(&self) -> &[u8] {
+            self.wav
+        }
+
+        /// DLLが返したバッファーのうち､WAVデータとして有効な部分の長さを返します
+        /// バッファーの末尾に余分なバイトがある場合はそれを除いた長さになるので､ファイルに書き出すときは&wav[..valid_len]を使うと余分なバイトを含みません
+        pub fn valid_len(&self) -> Result<usize, WavError> {
+            Ok(WavHeader::parse(self.wav)?.total_len)
+        }
+
+        /// ヘッダーのサンプリングレートをrateに書き換えます｡リサンプリングはしません
+        /// PCMデータはそのままなので､再生速度と音の高さが変わります(サンプリングレートを2倍にすると2倍速で高い声になります)
+        pub fn set_sample_rate_tag(&mut self, rate: u32) -> Result<(), WavError> {
+            wav::set_sample_rate_tag(self.wav, rate)
+        }
+
+        /// WAVデータの有効な部分(valid_len)だけをコピーしてOwnedWavにします
+        /// DLLを開放した後もWAVデータを使いたい場合や､with_metadataでメタデータを付けたい場合に使います
+        pub fn to_owned_wav(&self) -> Result<wav::OwnedWav, WavError> {
+            wav::OwnedWav::new(self.wav[..self.valid_len()?].to_vec())
+        }
+
+        /// WAVデータ全体を読み込むCursorを返します
+        /// Read + Seekを受け取るAPIに､コピーせずにそのまま渡せます
+        pub fn reader(&self) -> Cursor<&[u8]> {
+            Cursor::new(self.wav)
+        }
+
+        /// PCMデータを16bitのサンプルのスライスとして､コピーせずに返します
+        /// 16bitリニアPCMでない場合のほか､データが2バイト境界に揃っていない場合やビッグエンディアンの環境ではエラーになるので､その場合はto_pcm_i16を使ってください
+        pub fn as_i16_samples(&self) -> Result<&[i16], WavError> {
+            let header = WavHeader::parse(self.wav)?;
+            if header.format_tag != 1 || header.bits_per_sample != 16 {
+                return Err(WavError::UnsupportedFormat);
+            }
+            if cfg!(target_endian = "big") {
+                return Err(WavError::Unaligned);
+            }
+            let data = &self.wav[header.data_offset..header.data_offset + header.data_len / 2 * 2];
+            let (head, samples, tail) = unsafe { data.align_to::<i16>() };
+            if head.is_empty() && tail.is_empty() {
+                Ok(samples)
+            } else {
+                Err(WavError::Unaligned)
+            }
+        }
+
+        /// 音声の長さを返します
+        pub fn duration(&self) -> Result<Duration, WavError> {
+            Ok(WavHeader::parse(self.wav)?.duration())
+        }
+
+        /// 振幅の絶対値がthresholdより小さいサンプルの割合を､0.0から1.0の範囲で返します
+        /// 1.0に近い場合はほとんど無音で､変換や合成に失敗している可能性があるので､自動での品質チェックに使えます｡サンプルが1つもない場合も1.0を返します
+        pub fn silence_ratio(&self, threshold: i16) -> Result<f64, WavError> {
+            let pcm = self.to_pcm_i16()?;
+            if pcm.is_empty() {
+                return Ok(1.0);
+            }
+            let silent = pcm
+                .iter()
+                .filter(|s| i32::from(**s).abs() < i32::from(threshold))
+                .count();
+            Ok(silent as f64 / pcm.len() as f64)
+        }
+
+        /// 元のテキストの文字数source_char_countから､1秒あたりに話している文字数を計算します
+        /// WAVデータだけでは元のテキストの長さが分からないので､文字数は引数で渡してください｡音声の長さが0の場合は0.0を返します
+        pub fn chars_per_second(&self, source_char_count: usize) -> Result<f64, WavError> {
+            let secs = self.duration()?.as_secs_f64();
+            if secs == 0.0 {
+                Ok(0.0)
+            } else {
+                Ok(source_char_count as f64 / secs)
+            }
+        }
+
+        /// 16bitのサンプルの列に変換します
+        pub fn to_pcm_i16(&self) -> Result<Vec<i16>, WavError> {
+            wav::pcm_i16(self.wav)
+        }
+
+        /// 16bitのサンプルをビッグエンディアンのバイト列に変換します
+        /// WAVデータはリトルエンディアンなので､ビッグエンディアンを受け付けるDACなどに直接渡したい場合に使います
+        pub fn to_pcm_i16_be(&self) -> Result<Vec<u8>, WavError> {
+            Ok(self
+                .to_pcm_i16()?
+                .into_iter()
+                .flat_map(i16::to_be_bytes)
+                .collect())
+        }
+
+        /// 16bitのサンプルを32768.0で割って､-1.0から1.0の範囲のf32のサンプルの列に変換します
+        /// エフェクトや機械学習のモデルに渡すときに使えます
+        pub fn to_pcm_f32(&self) -> Result<Vec<f32>, WavError> {
+            Ok(self
+                .to_pcm_i16()?
+                .into_iter()
+                .map(|s| f32::from(s) / 32768.0)
+                .collect())
+        }
+
+        /// サンプルをsizeサンプルずつ､hopサンプルずつずらしながら切り出し､ハン窓をかけたフレームを返します
+        /// 詳しくはwav::windowsを見てください
+        pub fn windows(
+            &self,
+            size: usize,
+            hop: usize,
+        ) -> Result<impl Iterator<Item = Vec<f32>>, WavError> {
+            wav::windows(self.wav, size, hop)
+        }
+
+        /// PCMデータに1次のローパスフィルターをかけて､その場で書き換えます｡cutoff_hzは遮断周波数です
+        /// 詳しくはwav::low_passを見てください
+        pub fn low_pass(&mut self, cutoff_hz: f32) -> Result<(), WavError> {
+            wav::low_pass(self.wav, cutoff_hz)
+        }
+
+        /// 16bitのサンプルを上位8bitだけ残して､8bitの符号なしのサンプル(無音が128)の列に変換します
+        /// 下位8bitは捨てるので音質は落ち､小さな音にはノイズが目立ちます｡8bitしか扱えない古い機器や組み込み機器向けです
+        /// WAVデータにするにはwav::write_wav_u8を使ってください
+        pub fn to_pcm_u8(&self) -> Result<Vec<u8>, WavError> {
+            wav::pcm_u8(self.wav)
+        }
+
+        /// 2つのWAVデータのサンプルを比べて､全てのサンプルの差がtolerance以下ならtrueを返します
+        /// チャンネル数やサンプリングレート､長さが違う場合はfalseを返します
+        pub fn pcm_approx_eq(&self, other: &AqWAV, tolerance: i16) -> Result<bool, WavError> {
+            let (header, header2) = (WavHeader::parse(self.wav)?, WavHeader::parse(other.wav)?);
+            if header.channels != header2.channels || header.sample_rate != header2.sample_rate {
+                return Ok(false);
+            }
+            let (pcm, pcm2) = (self.to_pcm_i16()?, other.to_pcm_i16()?);
+            Ok(pcm.len() == pcm2.len()
+                && pcm
+                    .iter()
+                    .zip(pcm2.iter())
+                    .all(|(a, b)| (i32::from(*a) - i32::from(*b)).abs() <= i32::from(tolerance)))
+        }
+    }
+
+    impl<'a> AsRef<[u8]> for AqWAV<'a> {
+        fn as_ref(&self) -> &[u8] {
+            self.wav
+        }
     }
 
     impl<'a> std::ops::Deref for AqWAV<'a> {
-        type Target = &'a mut [u8];
+        type Target = [u8];
 
         fn deref(&self) -> &Self::Target {
-            &self.wav
+            self.wav
         }
     }
 
     impl<'a> std::ops::DerefMut for AqWAV<'a> {
         fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.wav
+            self.wav
         }
     }
 
     impl<'a> std::ops::Drop for AqWAV<'a> {
         fn drop(&mut self) {
-            unsafe {
-                (self.dll.freewav)(&mut self.wav[0] as *mut u8);
+            // 長さが0でもスライスはDLLが返したポインタを指しているので､nullでなければ開放します
+            let wav = self.wav.as_mut_ptr();
+            if !wav.is_null() {
+                unsafe {
+                    (self.dll.freewav)(wav);
+                }
             }
         }
     }
 
-    struct AqErr(i32);
+    /// # AquesTalk1のラッパーが返すエラー
+    /// Box<dyn std::error::Error>からdowncast_refで取り出せます
+    pub enum Aq1Error {
+        /// DLLが返したエラーコードです
+        Code(i32),
+        /// DLLから関数を読み込めませんでした｡読み込めなかった関数名と､libloadingのエラーを持っています
+        MissingSymbol {
+            symbol: &'static str,
+            source: libloading::Error,
+        },
+        /// AquesTalk.dllの代わりにAqKanji2Koe.dllが指定されました
+        WrongDll,
+        /// 発話速度が50-300の範囲外でした
+        InvalidSpeed(i32),
+        /// 音声記号列がset_max_koe_lenで設定した上限より長いため､DLLに渡しませんでした｡音声記号列の長さと上限(どちらもバイト単位)を持っています
+        InputTooLong { len: usize, max: usize },
+        /// DLLにこの関数がないため使えません｡関数名を持っています
+        SymbolUnavailable(&'static str),
+        /// DLLが0以下か大きすぎるWAVデータの長さを返しました｡DLLが返した長さを持っています
+        InvalidWavSize(i32),
+    }
+
+    /// AquesTalk1のDLLが返すエラーコードと､そのメッセージの一覧です
+    const CODES: &[(i32, &str)] = &[
+        (100, "その他のエラー, エラーコード: 100"),
+        (101, "メモリ不足, エラーコード: 101"),
+        (102, "音声記号列に未定義の読み記号が指定された, エラーコード: 102"),
+        (103, "韻律データの時間長がマイナスなっている, エラーコード: 103"),
+        (104, "内部エラー(未定義の区切りコード検出）, エラーコード: 104"),
+        (105, "音声記号列に未定義の読み記号が指定された, エラーコード: 105"),
+        (106, "音声記号列のタグの指定が正しくない, エラーコード: 106"),
+        (107, "タグの長さが制限を越えている（または[>]がみつからない）, エラーコード: 107"),
+        (108, "タグ内の値の指定が正しくない, エラーコード: 108"),
+        (109, "WAVE再生ができない（サウンドドライバ関連の問題）, エラーコード: 109"),
+        (110, "WAVE再生ができない（サウンドドライバ関連の問題非同期再生）, エラーコード: 110"),
+        (111, "発声すべきデータがない, エラーコード: 111"),
+        (200, "音声記号列が長すぎる(synthe_long_to_fileを使うか､音声記号列を分けてください), エラーコード: 200"),
+        (201, "１つのフレーズ中の読み記号が多すぎる(/や、でフレーズを区切るか､phonetics::split_long_phrasesで区切りを入れてください), エラーコード: 201"),
+        (202, "音声記号列が長い（内部バッファオーバー1）, エラーコード: 202"),
+        (203, "ヒープメモリ不足, エラーコード: 203"),
+        (204, "音声記号列が長い（内部バッファオーバー1）(synthe_long_to_fileを使うか､音声記号列を分けてください), エラーコード: 204"),
+    ];
+
+    /// CODESの英語版です
+    const CODES_EN: &[(i32, &str)] = &[
+        (100, "Other error, error code: 100"),
+        (101, "Out of memory, error code: 101"),
+        (102, "Undefined reading symbol in the phonetic string, error code: 102"),
+        (103, "Negative duration in prosody data, error code: 103"),
+        (104, "Internal error (undefined delimiter code), error code: 104"),
+        (105, "Undefined reading symbol in the phonetic string, error code: 105"),
+        (106, "Invalid tag in the phonetic string, error code: 106"),
+        (107, "Tag too long (or closing '>' not found), error code: 107"),
+        (108, "Invalid value inside a tag, error code: 108"),
+        (109, "Cannot play WAVE (sound driver problem), error code: 109"),
+        (110, "Cannot play WAVE (sound driver problem, async playback), error code: 110"),
+        (111, "No data to speak, error code: 111"),
+        (200, "Phonetic string too long (use synthe_long_to_file or split the string), error code: 200"),
+        (201, "Too many reading symbols in one phrase (split phrases with '/' or '、', or use phonetics::split_long_phrases), error code: 201"),
+        (202, "Phonetic string too long (internal buffer overflow 1), error code: 202"),
+        (203, "Out of heap memory, error code: 203"),
+        (204, "Phonetic string too long (internal buffer overflow 1) (use synthe_long_to_file or split the string), error code: 204"),
+    ];
+
+    impl Aq1Error {
+        /// Displayで表示されるメッセージ(日本語)の英語版を返します
+        pub fn message_en(&self) -> &str {
+            match self {
+                Aq1Error::Code(code) => CODES_EN
+                    .iter()
+                    .find(|(c, _)| c == code)
+                    .map_or("Undefined error", |(_, m)| m),
+                Aq1Error::InvalidSpeed(_) => "Speech speed is out of the range 50-300",
+                Aq1Error::InputTooLong { .. } => {
+                    "Phonetic string too long (use synthe_long_to_file or split the string)"
+                }
+                Aq1Error::SymbolUnavailable(_) => "The DLL does not export this function",
+                Aq1Error::InvalidWavSize(_) => "The DLL returned an invalid WAV length",
+                Aq1Error::WrongDll => {
+                    "AqKanji2Koe.dll was given instead of AquesTalk.dll (load it with AqK2KDLL::load)"
+                }
+                Aq1Error::MissingSymbol { .. } => {
+                    "Function not found in the DLL (the path may not point to AquesTalk.dll)"
+                }
+            }
+        }
+
+        /// DLLが返すエラーコードと､そのメッセージの一覧を返します
+        /// メッセージの翻訳表を作るときなどに使えます
+        pub fn all_codes() -> &'static [(i32, &'static str)] {
+            CODES
+        }
+
+        /// DLLが返したエラーコードをそのまま返します｡DLLのエラーコードでないエラーの場合はNoneを返します
+        pub fn code(&self) -> Option<i32> {
+            match self {
+                Aq1Error::Code(code) => Some(*code),
+                _ => None,
+            }
+        }
 
-    impl AqErr {
         fn msg(&self) -> &str {
-            match self.0 {
-                100 => "その他のエラー, エラーコード: 100",
-                101 => "メモリ不足, エラーコード: 101",
-                102 => "音声記号列に未定義の読み記号が指定された, エラーコード: 102",
-                103 => "韻律データの時間長がマイナスなっている, エラーコード: 103",
-                104 => "内部エラー(未定義の区切りコード検出）, エラーコード: 104",
-                105 => "音声記号列に未定義の読み記号が指定された, エラーコード: 105",
-                106 => "音声記号列のタグの指定が正しくない, エラーコード: 106",
-                107 => "タグの長さが制限を越えている（または[>]がみつからない）, エラーコード: 107",
-                108 => "タグ内の値の指定が正しくない, エラーコード: 108",
-                109 => "WAVE再生ができない（サウンドドライバ関連の問題）, エラーコード: 109",
-                110 => {
-                    "WAVE再生ができない（サウンドドライバ関連の問題非同期再生）, エラーコード: 110"
-                }
-                111 => "発声すべきデータがない, エラーコード: 111",
-                200 => "音声記号列が長すぎる, エラーコード: 200",
-                201 => "１つのフレーズ中の読み記号が多すぎる, エラーコード: 201",
-                202 => "音声記号列が長い（内部バッファオーバー1）, エラーコード: 202",
-                203 => "ヒープメモリ不足, エラーコード: 203",
-                204 => "音声記号列が長い（内部バッファオーバー1）, エラーコード: 204",
-                _ => "未定義のエラー",
-            }
-        }
-    }
-
-    impl std::fmt::Display for AqErr {
+            match self {
+                Aq1Error::Code(code) => CODES
+                    .iter()
+                    .find(|(c, _)| c == code)
+                    .map_or("未定義のエラー", |(_, m)| m),
+                Aq1Error::InvalidSpeed(_) => "発話速度が50-300の範囲外",
+                Aq1Error::InputTooLong { .. } => {
+                    "音声記号列が長すぎる(synthe_long_to_fileを使うか､音声記号列を分けてください)"
+                }
+                Aq1Error::SymbolUnavailable(_) => "DLLにこの関数がない",
+                Aq1Error::InvalidWavSize(_) => "DLLが返したWAVデータの長さが不正",
+                Aq1Error::WrongDll => {
+                    "AquesTalk.dllではなくAqKanji2Koe.dllが指定されている(AqK2KDLL::loadで読み込んでください)"
+                }
+                Aq1Error::MissingSymbol { .. } => {
+                    "DLLに関数が見つからない(指定したパスがAquesTalk.dllではない可能性があります)"
+                }
+            }
+        }
+    }
+
+    impl std::fmt::Display for Aq1Error {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.msg())
+            match self {
+                Aq1Error::MissingSymbol { symbol, source } => {
+                    write!(f, "{}, 関数名: {}, 詳細: {}", self.msg(), symbol, source)
+                }
+                Aq1Error::InvalidSpeed(ispeed) => {
+                    write!(f, "{}, 指定された値: {}", self.msg(), ispeed)
+                }
+                Aq1Error::InputTooLong { len, max } => {
+                    write!(f, "{}, 長さ: {}, 上限: {}", self.msg(), len, max)
+                }
+                Aq1Error::SymbolUnavailable(symbol) => {
+                    write!(f, "{}, 関数名: {}", self.msg(), symbol)
+                }
+                Aq1Error::InvalidWavSize(size) => write!(f, "{}, 長さ: {}", self.msg(), size),
+                _ => write!(f, "{}", self.msg()),
+            }
         }
     }
 
-    impl std::fmt::Debug for AqErr {
+    impl std::fmt::Debug for Aq1Error {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.msg())
+            write!(f, "{}", self)
         }
     }
 
-    impl std::error::Error for AqErr {
+    impl std::error::Error for Aq1Error {
         fn description(&self) -> &str {
             self.msg()
         }
+
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Aq1Error::MissingSymbol { source, .. } => Some(source),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mock::{self, MockLib};
+
+        #[test]
+        fn mock_synthe() {
+            let dll = mock::aquestalk();
+            let wav = dll.synthe("ゆっくり", 100).unwrap();
+            let header = WavHeader::parse(&wav).unwrap();
+            assert_eq!(header.spec(), mock::SPEC);
+            assert_eq!(header.duration(), mock::CHAR_DURATION * 4);
+        }
+
+        /// AqDLLとleakしたAqStaticDLLのsyntheの速さを比べます
+        /// 時間がかかるので普段は実行しません｡cargo test --release -- --ignored --nocapture synthe_leak_benchmark で実行してください
+        #[test]
+        #[ignore]
+        fn synthe_leak_benchmark() {
+            const N: u32 = 100_000;
+            let dll = mock::aquestalk();
+            let leaked = dll.clone().leak();
+            let start = Instant::now();
+            for _ in 0..N {
+                drop(dll.synthe("あ", 100).unwrap());
+            }
+            let shared = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..N {
+                drop(leaked.synthe("あ", 100).unwrap());
+            }
+            let leaked = start.elapsed();
+            println!("AqDLL::synthe: {:?}/回", shared / N);
+            println!("AqStaticDLL::synthe: {:?}/回", leaked / N);
+        }
+
+        #[test]
+        fn missing_synthe_symbol() {
+            let missing = |lib: MockLib| match unsafe { AqDLL::from_source(lib) } {
+                Err(Aq1Error::MissingSymbol { symbol, .. }) => symbol,
+                _ => panic!("MissingSymbolになりませんでした"),
+            };
+            let e = unsafe {
+                AqDLL::from_source(MockLib::aquestalk().without("AquesTalk_Synthe_Utf8"))
+            }
+            .err()
+            .unwrap();
+            assert!(std::error::Error::source(&e).is_some());
+            assert!(e.to_string().contains("AquesTalk_Synthe_Utf8"));
+            assert_eq!(
+                missing(MockLib::aquestalk().without("AquesTalk_FreeWave")),
+                "AquesTalk_FreeWave"
+            );
+            // 声のデータのファイルのように､何もエクスポートしていない場合
+            let empty = MockLib::aquestalk()
+                .without("AquesTalk_Synthe_Utf8")
+                .without("AquesTalk_FreeWave");
+            assert_eq!(missing(empty), "AquesTalk_Synthe_Utf8");
+        }
+
+        #[test]
+        fn synthe_retry_transient_errors() {
+            let dll = mock::aquestalk();
+            let code = |r: Result<AqWAV, Box<dyn std::error::Error>>| {
+                r.err()
+                    .unwrap()
+                    .downcast_ref::<Aq1Error>()
+                    .and_then(Aq1Error::code)
+            };
+            mock::take_synthe_calls();
+            mock::push_synthe(mock::SyntheResult::Error(109));
+            mock::push_synthe(mock::SyntheResult::Error(110));
+            assert!(dll.synthe_retry("あ", 100, 3).is_ok());
+            assert_eq!(mock::take_synthe_calls().len(), 3);
+
+            // 回数を使い切った場合は最後のエラーを返します
+            for _ in 0..3 {
+                mock::push_synthe(mock::SyntheResult::Error(110));
+            }
+            assert_eq!(code(dll.synthe_retry("あ", 100, 3)), Some(110));
+            assert_eq!(mock::take_synthe_calls().len(), 3);
+
+            // 109と110以外のエラーは再試行しません
+            mock::push_synthe(mock::SyntheResult::Error(105));
+            assert_eq!(code(dll.synthe_retry("あ", 100, 3)), Some(105));
+            assert_eq!(mock::take_synthe_calls().len(), 1);
+        }
+
+        #[test]
+        fn wrong_dll() {
+            assert!(matches!(
+                unsafe { AqDLL::from_source(MockLib::aqkanji2koe()) },
+                Err(Aq1Error::WrongDll)
+            ));
+        }
+
+        #[test]
+        fn error_code_table() {
+            let table = [
+                (Aq1Error::Code(102), Some(102)),
+                (Aq1Error::Code(204), Some(204)),
+                (
+                    Aq1Error::MissingSymbol {
+                        symbol: "AquesTalk_Synthe_Utf8",
+                        source: libloading::Error::DlSymUnknown,
+                    },
+                    None,
+                ),
+                (Aq1Error::WrongDll, None),
+                (Aq1Error::InvalidSpeed(400), None),
+                (Aq1Error::InputTooLong { len: 7, max: 6 }, None),
+                (Aq1Error::SymbolUnavailable("AquesTalk_Synthe_Utf8"), None),
+                (Aq1Error::InvalidWavSize(-1), None),
+            ];
+            for (e, code) in table.iter() {
+                assert_eq!(e.code(), *code, "{}", e);
+            }
+        }
+
+        #[test]
+        fn all_codes_round_trip() {
+            for (code, _) in Aq1Error::all_codes() {
+                assert_eq!(Aq1Error::Code(*code).code(), Some(*code));
+            }
+        }
+
+        #[test]
+        fn all_codes_have_messages() {
+            for (code, message) in Aq1Error::all_codes() {
+                let e = Aq1Error::Code(*code);
+                assert!(!message.is_empty());
+                assert_eq!(e.to_string(), *message);
+                assert!(!e.message_en().is_empty());
+                assert_ne!(e.message_en(), "Undefined error", "{}", code);
+            }
+        }
+
+        #[test]
+        fn drop_empty_wav() {
+            let dll = mock::aquestalk();
+            let (wav, _) = dll.synthe_leak("あ", 100).unwrap();
+            mock::take_freed();
+            let empty = unsafe { AqWAV::from_raw(&dll, wav, 0) };
+            assert!(empty.is_empty());
+            drop(empty);
+            assert_eq!(mock::take_freed(), 1);
+        }
+
+        #[test]
+        fn synthe_bytes() {
+            let dll = mock::aquestalk();
+            mock::take_synthe_calls();
+            let wav = dll.synthe_bytes("あい".as_bytes(), 100).unwrap();
+            assert_eq!(
+                WavHeader::parse(&wav).unwrap().duration(),
+                mock::CHAR_DURATION * 2
+            );
+            assert_eq!(mock::take_synthe_calls(), vec!["あい"]);
+            // NULを含む場合はDLLを呼ばずにエラーにします
+            let e = dll.synthe_bytes(b"a\0i", 100).err().unwrap();
+            assert!(e.downcast_ref::<std::ffi::NulError>().is_some());
+            assert!(mock::take_synthe_calls().is_empty());
+        }
+
+        #[test]
+        fn synthe_leak_checks_wav() {
+            let mut dll = mock::aquestalk();
+            dll.set_check_wav(true);
+            mock::take_freed();
+            mock::push_synthe(mock::SyntheResult::Data(b"not a wav".to_vec()));
+            let e = dll.synthe_leak("あ", 100).err().unwrap();
+            assert!(e.downcast_ref::<WavError>().is_some());
+            // 不正なデータは返さずに開放します
+            assert_eq!(mock::take_freed(), 1);
+            let (wav, len) = dll.synthe_leak("あ", 100).unwrap();
+            unsafe { drop(AqWAV::from_raw(&dll, wav, len)) };
+            assert_eq!(mock::take_freed(), 1);
+        }
+
+        #[test]
+        fn discover_voices_in_temp_dir() {
+            let base = env::temp_dir().join(format!("aquestalk_rs-voices-{}", process::id()));
+            let _ = fs::remove_dir_all(&base);
+            for dir in ["f1", "custom", "empty", "other", "m1"].iter() {
+                fs::create_dir_all(base.join(dir)).unwrap();
+            }
+            fs::write(base.join("f1").join(default_library_name()), b"").unwrap();
+            fs::write(base.join("custom").join(default_library_name()), b"").unwrap();
+            fs::write(base.join("other").join("voice.bin"), b"").unwrap();
+            // ライブラリと同じ名前でもディレクトリなら声にしません
+            fs::create_dir(base.join("m1").join(default_library_name())).unwrap();
+            // 声のディレクトリと同じ名前のファイル
+            fs::write(base.join("f2"), b"").unwrap();
+
+            let voices = discover_voices(&base).unwrap();
+            fs::remove_dir_all(&base).unwrap();
+            let found: Vec<_> = voices.iter().map(|v| (v.name.as_str(), v.voice)).collect();
+            assert_eq!(found, vec![("custom", None), ("f1", Some(Voice::F1))]);
+            assert_eq!(voices[1].path, Voice::F1.path(&base));
+            assert!(discover_voices(&base).is_err());
+        }
+
+        #[test]
+        fn max_koe_len_is_opt_in() {
+            let mut dll = mock::aquestalk();
+            mock::take_synthe_calls();
+            // 初期設定では長さを確かめずにDLLに渡します
+            assert_eq!(dll.max_koe_len(), None);
+            assert!(dll.synthe(&"あ".repeat(MAX_KOE_LEN), 100).is_ok());
+            assert_eq!(mock::take_synthe_calls().len(), 1);
+
+            dll.set_max_koe_len(Some(6));
+            // ちょうど上限の長さはDLLに渡します
+            assert!(dll.synthe("ああ", 100).is_ok());
+            assert_eq!(mock::take_synthe_calls(), vec!["ああ"]);
+            // 1バイトでも超えればDLLを呼ばずにエラーにします
+            let e = dll.synthe("ああa", 100).err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<Aq1Error>(),
+                Some(Aq1Error::InputTooLong { len: 7, max: 6 })
+            ));
+            assert!(dll.synthe_bytes(b"aaaaaaa", 100).is_err());
+            assert!(dll.clone().leak().synthe("ああa", 100).is_err());
+            assert!(mock::take_synthe_calls().is_empty());
+        }
+
+        #[test]
+        fn invalid_wav_size() {
+            let dll = mock::aquestalk();
+            mock::take_freed();
+            for size in [0, -1, i32::MIN, MAX_WAV_LEN as i32 + 1].iter() {
+                mock::push_synthe(mock::SyntheResult::Size(*size));
+                let e = dll.synthe("あ", 100).err().unwrap();
+                assert!(matches!(
+                    e.downcast_ref::<Aq1Error>(),
+                    Some(Aq1Error::InvalidWavSize(s)) if s == size
+                ));
+                // スライスを作らずに､バッファーを開放します
+                assert_eq!(mock::take_freed(), 1);
+            }
+        }
+
+        #[test]
+        fn clones_in_threads() {
+            fn assert_send_sync<T: Send + Sync>() {}
+            assert_send_sync::<AqDLL>();
+            let dll = mock::aquestalk();
+            let handles: Vec<_> = (1..=4)
+                .map(|n| {
+                    let dll = dll.clone();
+                    thread::spawn(move || {
+                        let wav = dll.synthe(&"あ".repeat(n), 100).unwrap();
+                        WavHeader::parse(&wav).unwrap().duration()
+                    })
+                })
+                .collect();
+            for (n, handle) in (1..=4).zip(handles) {
+                assert_eq!(handle.join().unwrap(), mock::CHAR_DURATION * n);
+            }
+            // スレッドに渡したクローンとAqWAVは全てDropされています
+            assert_eq!(dll.active_references(), 0);
+        }
+
+        #[test]
+        fn into_raw_frees_once() {
+            let dll = mock::aquestalk();
+            mock::take_freed();
+            let (wav, len) = dll.synthe("あ", 100).unwrap().into_raw();
+            // into_rawしたWAVデータは開放されず､DLLへの参照だけを手放します
+            assert_eq!(mock::take_freed(), 0);
+            assert_eq!(dll.active_references(), 0);
+            let wav = unsafe { AqWAV::from_raw(&dll, wav, len) };
+            assert_eq!(dll.active_references(), 1);
+            let (wav, _) = wav.into_raw();
+            unsafe { dll.free_wav(wav) };
+            assert_eq!(mock::take_freed(), 1);
+        }
+
+        #[test]
+        fn synthe_phonemes_units() {
+            let dll = mock::aquestalk();
+            let koe = "きゃっと'/_しー";
+            let pieces = dll.synthe_phonemes(koe, 100).unwrap();
+            let units: Vec<_> = pieces.iter().map(|(unit, _)| unit.as_str()).collect();
+            assert_eq!(units, vec!["きゃっ", "と", "_しー"]);
+            // 偽のDLLは文字数に比例した長さの音声を返すので､拍ごとの長さの合計は全体の長さと同じになります
+            let total: Duration = pieces.iter().map(|(_, wav)| wav.header().duration()).sum();
+            let whole = dll.synthe(koe, 100).unwrap();
+            assert_eq!(total, WavHeader::parse(&whole).unwrap().duration());
+        }
+
+        #[test]
+        fn active_references_counts_wavs_and_clones() {
+            let dll = mock::aquestalk();
+            assert_eq!(dll.active_references(), 0);
+            let a = dll.synthe("あ", 100).unwrap();
+            let b = dll.synthe("い", 100).unwrap();
+            assert_eq!(dll.active_references(), 2);
+            let clone = dll.clone();
+            assert_eq!(dll.active_references(), 3);
+            drop((a, clone));
+            assert_eq!(dll.active_references(), 1);
+            drop(b);
+            assert_eq!(dll.active_references(), 0);
+        }
+
+        #[test]
+        fn load_from_bytes_removes_temp_file_on_error() {
+            assert!(AqDLL::load_from_bytes(b"not a dll").is_err());
+            let prefix = format!("aquestalk_rs-{}-", process::id());
+            let left = fs::read_dir(env::temp_dir())
+                .unwrap()
+                .filter_map(Result::ok)
+                .any(|e| e.file_name().to_string_lossy().starts_with(&prefix));
+            assert!(!left);
+        }
+
+        #[test]
+        #[cfg(target_os = "windows")]
+        fn library_name_windows() {
+            assert_eq!(default_library_name(), "AquesTalk.dll");
+        }
+
+        #[test]
+        #[cfg(target_os = "macos")]
+        fn library_name_macos() {
+            assert_eq!(default_library_name(), "libAquesTalk.dylib");
+        }
+
+        #[test]
+        #[cfg(target_os = "linux")]
+        fn library_name_linux() {
+            assert_eq!(default_library_name(), "libAquesTalk.so");
+            assert_eq!(
+                Voice::F1.path("aquestalk"),
+                Path::new("aquestalk/f1/libAquesTalk.so")
+            );
+        }
+
+        #[test]
+        fn speed_policy_at_400() {
+            assert_eq!(SpeedPolicy::Clamp.apply(400).unwrap(), 300);
+            assert_eq!(SpeedPolicy::Clamp.apply(10).unwrap(), 50);
+            assert!(matches!(
+                SpeedPolicy::Error.apply(400),
+                Err(Aq1Error::InvalidSpeed(400))
+            ));
+            assert_eq!(SpeedPolicy::Error.apply(300).unwrap(), 300);
+        }
+
+        #[test]
+        fn split_koe_keeps_tags_whole() {
+            let koe = format!("{}<NUM VAL=3.14>です", "あ".repeat(338));
+            let chunks = split_koe(&koe, MAX_KOE_LEN);
+            assert_eq!(
+                chunks,
+                vec!["あ".repeat(338), "<NUM VAL=3.14>です".to_string()]
+            );
+            for chunk in chunks {
+                phonetics::parse(chunk).unwrap();
+            }
+        }
+
+        #[test]
+        fn split_koe_ignores_separators_inside_tags() {
+            // タグの中の/や,では切らず､その前の、で切ります
+            let koe = "あい、う<NUMK VAL=1,000/>え";
+            assert_eq!(
+                split_koe(koe, 20),
+                vec!["あい、", "う<NUMK VAL=1,000/>", "え"]
+            );
+        }
+
+        #[test]
+        fn split_koe_keeps_oversized_tag() {
+            let koe = "<ALPHA VAL=abcdefghij>あ";
+            assert_eq!(split_koe(koe, 8), vec!["<ALPHA VAL=abcdefghij>", "あ"]);
+        }
+
+        #[test]
+        fn split_koe_boundaries() {
+            assert!(split_koe("", 9).is_empty());
+            assert_eq!(split_koe("あいう", 9), vec!["あいう"]);
+            assert_eq!(split_koe("あい/うえ", 9), vec!["あい/", "うえ"]);
+            assert_eq!(split_koe("あい。うえ、お", 12), vec!["あい。", "うえ、お"]);
+            // 区切りがない場合は拗音やアクセント記号の途中で切りません
+            assert_eq!(split_koe("かきゃく", 7), vec!["か", "きゃ", "く"]);
+            assert_eq!(split_koe("あい'う", 6), vec!["あ", "い'", "う"]);
+        }
     }
 }
 
 /// # AqKanji2Koeのラッパー
 /// 基本的な流れとしてはAqKanji2Koe.dllを読み込む→インスタンスを生成する→漢字かな混じりのテキストを音声記号列に変換するというように使います
 /// ## Examples
-/// ```
+/// ```no_run
 /// use aquestalk_rs::aqkanji2koe::AqK2KDLL;
 ///
 /// fn main() {
 ///     let aqk2k = AqK2KDLL::load("./aqk2k/lib64/AqKanji2Koe.dll", None).unwrap();
 ///     let mut aqk2kins = aqk2k.create("./aqk2k/aq_dic").unwrap();
 ///     let word = aqk2kins.convert("ゆっくりしていってね", None).unwrap();
-///     println!("{}", *word);
+///     println!("{}", &*word);
 /// }
 /// ```
 #[allow(clippy::needless_doctest_main)]
 pub mod aqkanji2koe {
-    use libloading::{Library, Symbol};
-    use safety_breaker::{force_convert, ForceMut};
+    use crate::{phonetics, tags::ReadingOverride, text_normalize, SymbolSource};
+    use libloading::Library;
+    use safety_breaker::force_convert;
+    #[cfg(debug_assertions)]
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::{
         alloc,
+        collections::{HashMap, VecDeque},
         convert::TryFrom,
-        ffi::{c_void, CStr, CString, OsStr},
+        env,
+        ffi::{c_void, CStr, CString, OsStr, OsString},
+        fs,
+        marker::PhantomData,
         mem,
-        mem::MaybeUninit,
         os::raw::c_char,
+        path::{Path, PathBuf},
+        ptr,
         sync::Arc,
+        time::Instant,
     };
-    type AqK2Kcreate<'a> = Symbol<'a, unsafe extern "C" fn(*const c_char, *mut i32) -> *mut c_void>;
-    type AqK2Kcreateptr<'a> =
-        Symbol<'a, unsafe extern "C" fn(*const c_void, *const c_void, *mut i32) -> *mut c_void>;
-    type AqK2Krelease<'a> = Symbol<'a, unsafe extern "C" fn(*mut c_void)>;
-    type AqK2Ksetdevkey<'a> = Symbol<'a, unsafe extern "C" fn(*const c_char) -> i32>;
-    type AqK2Kconvert<'a> =
-        Symbol<'a, unsafe extern "C" fn(*mut c_void, *const c_char, *mut c_char, i32) -> i32>;
+    // AquesTalk1と同じく__stdcallに合わせていますが､AqKanji2KoeはC++で書かれていて例外を投げる可能性があるので､extern "system-unwind"として宣言しています
+    // extern "system"のままだとDLLから例外が伝わってきたときに未定義動作になりますが､"-unwind"を付けると例外はRustの関数を通り抜けて(途中のDropは実行されます)呼び出し元に伝わります
+    // Rustの側では例外を捕まえられず､catch_unwindやスレッドの一番外側まで伝わった時点でプロセスが異常終了します
+    type AqK2Kcreate = unsafe extern "system-unwind" fn(*const c_char, *mut i32) -> *mut c_void;
+    type AqK2Kcreateptr =
+        unsafe extern "system-unwind" fn(*const c_void, *const c_void, *mut i32) -> *mut c_void;
+    type AqK2Krelease = unsafe extern "system-unwind" fn(*mut c_void);
+    type AqK2Ksetdevkey = unsafe extern "system-unwind" fn(*const c_char) -> i32;
+    type AqK2Kconvert =
+        unsafe extern "system-unwind" fn(*mut c_void, *const c_char, *mut c_char, i32) -> i32;
 
     /// # DLL内の基本的な関数にアクセスするためのラッパー
+    /// DLLは参照カウント付きで持っていて､AqK2KDLLとそこから作られたAqK2Kinstanceが全てDropされるまで開放されません
+    ///
+    /// cloneしたAqK2KDLLは読み込んだDLLを共有し､DLLを読み込み直したり制限解除をやり直したりすることはありません
+    /// どのcloneからcreateしたAqK2Kinstanceも同じDLLを使いますが､辞書やインスタンスはcreateごとに別々に作られます
+    #[derive(Clone)]
     pub struct AqK2KDLL<'a> {
         dll: Arc<AqK2KDLL2<'a>>,
     }
 
+    /// load_with_envなどで開発ライセンスキーを読み取る環境変数の名前です
+    pub const DEVKEY_ENV: &str = "AQUESTALK_DEVKEY";
+
+    /// # AqK2KDLL::builderで作られる､DLLの読み込みの設定
+    /// 設定を指定し終わったらbuildでDLLを読み込みます
+    /// 変換の関数はUTF-8版(WindowsではAqKanji2Koe_Convert_utf8)だけを使います｡このクレートは文字コードの変換を持っていないので､Shift_JIS版を選ぶ設定はありません
+    #[derive(Debug, Clone)]
+    pub struct AqK2KDLLBuilder {
+        dllpath: OsString,
+        devkey: Option<String>,
+    }
+
+    impl AqK2KDLLBuilder {
+        /// 開発ライセンスキーを指定します｡指定しなければ制限解除はしません
+        pub fn dev_key(mut self, devkey: &str) -> Self {
+            self.devkey = Some(devkey.to_string());
+            self
+        }
+
+        /// 環境変数AQUESTALK_DEVKEYに開発ライセンスキーがあれば､それを指定します
+        pub fn dev_key_from_env(mut self) -> Self {
+            if let Some(devkey) = env::var_os(DEVKEY_ENV) {
+                self.devkey = Some(devkey.to_string_lossy().into_owned());
+            }
+            self
+        }
+
+        /// 指定した設定でDLLを読み込みます
+        pub fn build<'a>(self) -> Result<AqK2KDLL<'a>, Box<dyn std::error::Error>> {
+            AqK2KDLL::load_inner(&self.dllpath, self.devkey.as_deref())
+        }
+    }
+
     struct AqK2KDLL2<'a> {
         #[allow(dead_code)]
         cpp: Option<Library>,
-        lib: Library,
-        create: AqK2Kcreate<'a>,
-        create_ptr: AqK2Kcreateptr<'a>,
-        release: AqK2Krelease<'a>,
-        convert: AqK2Kconvert<'a>,
-        setdevkey: AqK2Ksetdevkey<'a>,
+        // 関数ポインタはlibが開放されるまで有効なので､AqK2KDLL2と一緒に持っておきます｡テストで偽の関数を使う場合はNoneです
+        #[allow(dead_code)]
+        lib: Option<Library>,
+        create: AqK2Kcreate,
+        // 配布物によってはAqKanji2Koe_Create_Ptrがないため､読み込めなかった場合はNoneにします
+        create_ptr: Option<AqK2Kcreateptr>,
+        release: AqK2Krelease,
+        convert: AqK2Kconvert,
+        // 公開している型の寿命引数に合わせるためのものです
+        _lib: PhantomData<&'a Library>,
     }
 
     impl<'a> AqK2KDLL<'a> {
         /// 第一引数にはAqKanji2Koe.dllのパスを､第二引数には開発ライセンスキーを持っていればSome("(ライセンスキー)")を､持っていなければNoneを指定してください
         /// なお､この制限解除機能は私は製品版を持ってなくてテストしていないので､動作保証はありません(不具合があったら私に製品版をプレゼントするなり､Githubにプルリク投げるなりしてください)
-        #[allow(clippy::uninit_assumed_init)]
+        /// DLLのパスはAqDLL::loadと同じく､日本語を含むパスでも読み込めます
         pub fn load<P: AsRef<OsStr>>(
             dllpath: P,
             devkey: Option<&str>,
         ) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut builder = Self::builder(dllpath);
+            if let Some(s) = devkey {
+                builder = builder.dev_key(s);
+            }
+            builder.build()
+        }
+
+        /// loadと同じですが､開発ライセンスキーを環境変数AQUESTALK_DEVKEYから読み取ります｡環境変数がなければライセンスキーなしで読み込みます
+        /// ライセンスキーをソースコードに書かずに済むので､CIや配布先で使うのに向いています
+        pub fn load_with_env<P: AsRef<OsStr>>(
+            dllpath: P,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            Self::builder(dllpath).dev_key_from_env().build()
+        }
+
+        /// 読み込みの設定を1つずつ指定してからDLLを読み込むためのAqK2KDLLBuilderを作ります
+        /// 第一引数にはAqKanji2Koe.dllのパスを指定してください
+        pub fn builder<P: AsRef<OsStr>>(dllpath: P) -> AqK2KDLLBuilder {
+            AqK2KDLLBuilder {
+                dllpath: dllpath.as_ref().to_os_string(),
+                devkey: None,
+            }
+        }
+
+        fn load_inner(
+            dllpath: &OsStr,
+            devkey: Option<&str>,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            debug_log!("AqKanji2Koe.dllを読み込みます: {:?}", dllpath);
             unsafe {
                 let libcpp = Self::cpp()?;
-                let dll = AqK2KDLL {
-                    dll: Arc::new(AqK2KDLL2 {
-                        cpp: libcpp,
-                        lib: Library::new(dllpath)?,
-                        create: MaybeUninit::uninit().assume_init(),
-                        create_ptr: MaybeUninit::uninit().assume_init(),
-                        release: MaybeUninit::uninit().assume_init(),
-                        convert: MaybeUninit::uninit().assume_init(),
-                        setdevkey: MaybeUninit::uninit().assume_init(),
-                    }),
-                };
-                *dll.dll.setdevkey.forcemut() = dll.dll.lib.get(b"AqKanji2Koe_SetDevKey")?;
-                if let Some(s) = devkey {
-                    let s2 = CString::new(s)?;
-                    let _ = (dll.dll.setdevkey)(s2.as_ptr());
-                }
-                *dll.dll.create.forcemut() = dll.dll.lib.get(b"AqKanji2Koe_Create")?;
-                *dll.dll.create_ptr.forcemut() = dll.dll.lib.get(b"AqKanji2Koe_Create_Ptr")?;
-                *dll.dll.release.forcemut() = dll.dll.lib.get(b"AqKanji2Koe_Release")?;
-                *dll.dll.convert.forcemut() = dll.dll.lib.get(Self::conv())?;
-                Ok(dll)
+                Self::from_source(Library::new(dllpath)?, libcpp, devkey)
+            }
+        }
+
+        /// sourceから関数を探してAqK2KDLLを作り､devkeyがあれば制限解除をします
+        /// load_innerの本体で､テストではDLLの代わりに偽の関数を渡すのに使います
+        /// # Safety
+        /// sourceの関数は､AqKanji2Koeの関数と同じ型でなければなりません
+        pub(crate) unsafe fn from_source<S: SymbolSource>(
+            source: S,
+            cpp: Option<Library>,
+            devkey: Option<&str>,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            if !source.has_symbol("AqKanji2Koe_Create") && source.has_symbol("AquesTalk_FreeWave") {
+                return Err(Box::new(AqK2KError::WrongDll));
             }
+            let setdevkey: AqK2Ksetdevkey = source.symbol("AqKanji2Koe_SetDevKey")?;
+            if let Some(s) = devkey {
+                let s2 = CString::new(s)?;
+                let _ = setdevkey(s2.as_ptr());
+            }
+            let create = source.symbol("AqKanji2Koe_Create")?;
+            let create_ptr = source.symbol("AqKanji2Koe_Create_Ptr").ok();
+            let release = source.symbol("AqKanji2Koe_Release")?;
+            let convert = source.symbol(Self::conv())?;
+            Ok(AqK2KDLL {
+                dll: Arc::new(AqK2KDLL2 {
+                    cpp,
+                    lib: source.into_library(),
+                    create,
+                    create_ptr,
+                    release,
+                    convert,
+                    _lib: PhantomData,
+                }),
+            })
         }
 
         #[cfg(target_os = "linux")]
@@ -256,17 +1870,18 @@ pub mod aqkanji2koe {
         }
 
         #[cfg(target_os = "windows")]
-        fn conv() -> &'static [u8] {
-            b"AqKanji2Koe_Convert_utf8"
+        fn conv() -> &'static str {
+            "AqKanji2Koe_Convert_utf8"
         }
 
         #[cfg(not(target_os = "windows"))]
-        fn conv() -> &'static [u8] {
-            b"AqKanji2Koe_Convert"
+        fn conv() -> &'static str {
+            "AqKanji2Koe_Convert"
         }
 
         /// 本家のAqKanji2Koe_Createに当たります
         /// 引数には辞書のあるディレクトリを指定してください
+        /// 1つのAqK2KDLLから何個でもインスタンスを作ることができ､辞書が違っていてもそれぞれ独立して使えます
         pub fn create<'b>(
             &self,
             pathdic: &str,
@@ -276,11 +1891,18 @@ pub mod aqkanji2koe {
             unsafe {
                 let instance = (self.dll.create)(pathdic2.as_ptr(), &mut errcode as *mut i32);
                 if instance.is_null() {
-                    Err(Box::new(AqK2Kerr(errcode)))
+                    Err(Box::new(AqK2KError::Code(errcode)))
                 } else {
                     Ok(AqK2Kinstance {
                         instance,
                         dll: Arc::clone(force_convert!(&self.dll, Arc<AqK2KDLL2>)),
+                        config: AqK2KConvertConfig::default(),
+                        dic: Vec::new(),
+                        dict_size: fs::metadata(Path::new(pathdic).join("aqdic.bin"))
+                            .ok()
+                            .map(|m| m.len() as usize),
+                        #[cfg(debug_assertions)]
+                        converting: AtomicBool::new(false),
                     })
                 }
             }
@@ -289,71 +1911,609 @@ pub mod aqkanji2koe {
         /// 本家のAqKanji2Koe_Create_Ptrに当たります
         /// 第一引数にはシステム辞書の先頭アドレスを､第二引数にはユーザ辞書の先頭アドレスを指定してください
         /// インスタンスの開放は自動で行いますが､辞書の開放は手動でしてください
+        /// DLLにAqKanji2Koe_Create_Ptrがない場合はAqK2KError::SymbolUnavailableを返します
         #[allow(clippy::missing_safety_doc)]
         pub unsafe fn create_ptr<'b>(
             &self,
             sysdic: *const c_void,
             userdic: *const c_void,
         ) -> Result<AqK2Kinstance<'b>, Box<dyn std::error::Error>> {
+            let create_ptr = self
+                .dll
+                .create_ptr
+                .ok_or(AqK2KError::SymbolUnavailable("AqKanji2Koe_Create_Ptr"))?;
             let mut errcode: i32 = 0;
-            let instance = (self.dll.create_ptr)(sysdic, userdic, &mut errcode as *mut i32);
+            let instance = create_ptr(sysdic, userdic, &mut errcode as *mut i32);
             if instance.is_null() {
-                Err(Box::new(AqK2Kerr(errcode)))
+                Err(Box::new(AqK2KError::Code(errcode)))
             } else {
                 Ok(AqK2Kinstance {
                     instance,
                     // dll: Arc::clone(&*(&self.dll as *const _ as *mut Arc<AqK2KDLL2>)),
                     dll: Arc::clone(force_convert!(&self.dll, Arc<AqK2KDLL2>)),
+                    config: AqK2KConvertConfig::default(),
+                    dic: Vec::new(),
+                    dict_size: None,
+                    #[cfg(debug_assertions)]
+                    converting: AtomicBool::new(false),
                 })
             }
         }
+
+        /// create_ptrと同じですが､'staticなスライスで辞書を受け取ります
+        /// include_bytes!で埋め込んだ辞書をそのまま渡せるので､辞書のファイルを別に配布しなくても1つの実行ファイルで動かせます
+        /// スライスはプログラムが終わるまで有効なので､create_ptrと違ってunsafeは要りません｡userにはユーザ辞書を使う場合だけSomeを渡してください
+        pub fn create_from_static<'b>(
+            &self,
+            sys: &'static [u8],
+            user: Option<&'static [u8]>,
+        ) -> Result<AqK2Kinstance<'b>, Box<dyn std::error::Error>> {
+            let mut instance = unsafe {
+                self.create_ptr(
+                    sys.as_ptr() as *const c_void,
+                    user.map_or(ptr::null(), |u| u.as_ptr() as *const c_void),
+                )?
+            };
+            instance.dict_size = Some(sys.len());
+            Ok(instance)
+        }
+
+        /// createと同じですが､optionsでユーザ辞書の場所を辞書のディレクトリとは別に指定したり､ユーザ辞書を使わないようにしたりできます
+        /// ユーザ辞書を指定した場合や使わない場合は､システム辞書(pathdic/aqdic.bin)とユーザ辞書をメモリに読み込んでcreate_ptrでインスタンスを作ります
+        /// 読み込んだ辞書はインスタンスが持っていて､インスタンスと一緒に開放されます
+        pub fn create_with_options<'b>(
+            &self,
+            pathdic: &str,
+            options: AqK2KCreateOptions,
+        ) -> Result<AqK2Kinstance<'b>, Box<dyn std::error::Error>> {
+            let userdic = if options.without_user_dic {
+                None
+            } else {
+                match options.user_dic {
+                    Some(s) => Some(fs::read(s)?),
+                    None => return self.create(pathdic),
+                }
+            };
+            let sysdic = fs::read(Path::new(pathdic).join("aqdic.bin"))?;
+            unsafe {
+                let mut instance = self.create_ptr(
+                    sysdic.as_ptr() as *const c_void,
+                    userdic
+                        .as_ref()
+                        .map_or(ptr::null(), |u| u.as_ptr() as *const c_void),
+                )?;
+                instance.dict_size = Some(sysdic.len());
+                instance.dic.push(sysdic);
+                instance.dic.extend(userdic);
+                Ok(instance)
+            }
+        }
+
+        /// pathdicの辞書でインスタンスを作り､「あ」を1文字だけ変換してみて､辞書とDLLの組み合わせが正しいか確かめます
+        /// 辞書とDLLのバージョンが合っていない場合などに出る200番台のエラーは､AqK2KError::DictionaryMismatchにして返します
+        /// 起動時に呼んでおくと､配布物の組み合わせの間違いに早く気付けます
+        pub fn check_compatibility(&self, pathdic: &str) -> Result<(), Box<dyn std::error::Error>> {
+            let mismatch = |e: Box<dyn std::error::Error>| -> Box<dyn std::error::Error> {
+                match e.downcast_ref::<AqK2KError>().and_then(AqK2KError::code) {
+                    Some(code) if (200..=299).contains(&code) => {
+                        Box::new(AqK2KError::DictionaryMismatch(code))
+                    }
+                    _ => e,
+                }
+            };
+            let mut instance = self.create(pathdic).map_err(mismatch)?;
+            instance.convert("あ", None).map_err(mismatch)?;
+            Ok(())
+        }
+
+        /// ユーザ辞書を使わないインスタンスと使うインスタンスを作ります
+        /// ReadingComparison::diff_readingsで､ユーザ辞書の登録が読みに反映されているか確かめるのに使います
+        pub fn create_comparison<'b>(
+            &self,
+            pathdic: &str,
+            options: AqK2KCreateOptions,
+        ) -> Result<ReadingComparison<'b>, Box<dyn std::error::Error>> {
+            Ok(ReadingComparison {
+                system: self.create_with_options(
+                    pathdic,
+                    AqK2KCreateOptions {
+                        without_user_dic: true,
+                        ..options.clone()
+                    },
+                )?,
+                user: self.create_with_options(pathdic, options)?,
+            })
+        }
+    }
+
+    /// # create_with_optionsに渡す設定
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct AqK2KCreateOptions {
+        /// ユーザ辞書(aq_user.dic)のパスです｡Noneならcreateと同じく辞書のディレクトリにあるものを使います
+        pub user_dic: Option<PathBuf>,
+        /// trueにするとユーザ辞書を使わず､システム辞書だけでインスタンスを作ります
+        pub without_user_dic: bool,
+    }
+
+    /// # create_comparisonが返す､ユーザ辞書の有無だけが違う2つのインスタンス
+    pub struct ReadingComparison<'a> {
+        /// システム辞書だけを使うインスタンスです
+        pub system: AqK2Kinstance<'a>,
+        /// ユーザ辞書も使うインスタンスです
+        pub user: AqK2Kinstance<'a>,
+    }
+
+    /// # diff_readingsが返す､読みが違っていた部分
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ReadingDiff {
+        /// システム辞書だけで変換した読みです
+        pub system: String,
+        /// ユーザ辞書も使って変換した読みです
+        pub user: String,
+    }
+
+    impl<'a> ReadingComparison<'a> {
+        /// textを両方のインスタンスで変換し､読みが違っていたアクセント句を返します
+        /// 変換結果を/やポーズの記号で区切って順番に比べますが､区切りの数が違う場合は変換結果全体を1つの違いとして返します
+        pub fn diff_readings(
+            &mut self,
+            text: &str,
+        ) -> Result<Vec<ReadingDiff>, Box<dyn std::error::Error>> {
+            let system = self.system.convert(text, None)?.to_string();
+            let user = self.user.convert(text, None)?.to_string();
+            Ok(diff_phrases(system, user))
+        }
+    }
+
+    /// 2つの読みを区切りごとに比べて､違っている区切りを返します
+    /// 区切りの数が違う場合は､読み全体を1つの違いとして返します
+    fn diff_phrases(system: String, user: String) -> Vec<ReadingDiff> {
+        let split = |s: &str| -> Vec<String> {
+            s.split(['/', '、', '。', '？', ',', '.', '?', ';'])
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string())
+                .collect()
+        };
+        let (phrases, phrases2) = (split(&system), split(&user));
+        if phrases.len() != phrases2.len() {
+            return if system == user {
+                Vec::new()
+            } else {
+                vec![ReadingDiff { system, user }]
+            };
+        }
+        phrases
+            .into_iter()
+            .zip(phrases2)
+            .filter(|(a, b)| a != b)
+            .map(|(system, user)| ReadingDiff { system, user })
+            .collect()
     }
 
     /// # createやcreate_ptrが返すAqKanji2Koeのインスタンスのラッパー
     /// AqKanji2Koe_ReleaseはDrop時に実行されるため､自分で実行する必要はありません
+    /// 変換の状態はインスタンスごとに持っているため､別々のインスタンスなら別々のスレッドから同時にconvertできます
+    /// 1つのインスタンスを複数のスレッドで使いたい場合はMutexなどで包んでください(convertは&mut selfを取ります)
     pub struct AqK2Kinstance<'a> {
         instance: *mut c_void,
         dll: Arc<AqK2KDLL2<'a>>,
+        config: AqK2KConvertConfig,
+        // create_with_optionsで読み込んだ辞書｡インスタンスが開放されるまで持っておく必要があります
+        dic: Vec<Vec<u8>>,
+        // システム辞書の大きさ(バイト単位)｡create_ptrで作った場合はわからないのでNoneです
+        dict_size: Option<usize>,
+        // デバッグビルドでだけ､変換中かどうかを持っておいて入れ子の呼び出しを検出します
+        #[cfg(debug_assertions)]
+        converting: AtomicBool,
+    }
+
+    /// 変換中の印を立て､Drop時に下ろします
+    /// 同じインスタンスの変換中にもう一度変換が始まった場合(unsafeなコードで&mutを複製してしまった場合など)は､DLLの状態が壊れる前にpanicします
+    #[cfg(debug_assertions)]
+    struct ConvertGuard<'g>(&'g AtomicBool);
+
+    #[cfg(debug_assertions)]
+    impl<'g> ConvertGuard<'g> {
+        fn enter(converting: &'g AtomicBool) -> Self {
+            if converting.swap(true, Ordering::AcqRel) {
+                panic!("同じAqK2Kinstanceのconvertが入れ子で呼び出されました｡1つのインスタンスを複数の場所から同時に使わないでください");
+            }
+            ConvertGuard(converting)
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    impl<'g> Drop for ConvertGuard<'g> {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    /// # convertのバッファー確保の挙動を設定するための構造体
+    /// set_convert_configでインスタンスごとに設定できます｡Defaultは今までと同じ挙動になります
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AqK2KConvertConfig {
+        /// バッファーサイズの下限(バイト単位)です｡デフォルトは256です
+        pub min_buffer: usize,
+        /// 出力がバッファーに収まらなかったときに､バッファーを大きくして再試行する回数です｡デフォルトは0(再試行しない)です
+        /// 最後まで収まらなかった場合は､切れた出力を返さずにAqK2KError::BufferTooSmallを返します
+        pub max_retries: u32,
+        /// 再試行するときにバッファーサイズを何倍にするかです｡デフォルトは2で､2未満を指定しても2として扱います
+        pub multiplier: usize,
+    }
+
+    impl Default for AqK2KConvertConfig {
+        fn default() -> Self {
+            AqK2KConvertConfig {
+                min_buffer: 256,
+                max_retries: 0,
+                multiplier: 2,
+            }
+        }
     }
 
     impl<'a> AqK2Kinstance<'a> {
+        /// convertのバッファー確保の設定を変更します
+        pub fn set_convert_config(&mut self, config: AqK2KConvertConfig) {
+            self.config = config;
+        }
+
+        /// 現在のconvertのバッファー確保の設定を返します
+        pub fn convert_config(&self) -> AqK2KConvertConfig {
+            self.config
+        }
+
+        /// convertと同じですが､半角カタカナをtext_normalize::to_fullwidth_kanaで全角にしてから変換します
+        /// 貼り付けられたテキストのように､半角カタカナが混ざっているかもしれない入力に使ってください
+        pub fn convert_lenient<'b>(
+            &mut self,
+            kanji: &str,
+        ) -> Result<AqK2Kstr<'b>, Box<dyn std::error::Error>> {
+            self.convert(&text_normalize::to_fullwidth_kana(kanji), None)
+        }
+
+        /// convertで変換した読みを､アクセントやポーズの情報を持った簡単なマークアップにして返します
+        /// 形式はphonetics::to_markupを見てください｡phonetics::from_markupで解析し直せます
+        pub fn convert_to_markup(
+            &mut self,
+            kanji: &str,
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            let koe = self.convert(kanji, None)?;
+            Ok(phonetics::to_markup(&phonetics::parse(&koe)?))
+        }
+
+        /// システム辞書(aqdic.bin)の大きさ(バイト単位)を返します｡インスタンスが使うメモリの目安として､いくつインスタンスを作れるか見積もるのに使えます
+        /// createで作った場合はファイルの大きさ､create_with_optionsで作った場合は読み込んだ辞書の大きさです
+        /// create_ptrで作った場合は辞書の大きさがわからないのでNoneを返します
+        pub fn dict_memory_estimate(&self) -> Option<usize> {
+            self.dict_size
+        }
+
+        /// convertで変換した読みの長さ(UTF-8でのバイト単位)だけを返します
+        /// 変換結果はDLLに渡したバッファーの中で調べるので､Stringへのコピーはしません｡syntheに渡すバッファーの大きさを見積もるのに使えます
+        pub fn converted_len(&mut self, kanji: &str) -> Result<usize, Box<dyn std::error::Error>> {
+            Ok(self.convert(kanji, None)?.len())
+        }
+
+        /// convertで変換した読みを､仮名とアクセント核の位置に分けて返します
+        /// アクセントの高低を図にする場合などに使えます
+        pub fn convert_structured(
+            &mut self,
+            kanji: &str,
+        ) -> Result<phonetics::Reading, Box<dyn std::error::Error>> {
+            let koe = self.convert(kanji, None)?;
+            Ok(phonetics::Reading::from_moras(&phonetics::parse(&koe)?))
+        }
+
+        /// convertでエラーコード107(変換できない文字コード)になりそうな文字がないか調べます
+        /// DLLは呼ばずに､日本語の文字やASCIIなどShift_JISにある文字の範囲かどうかだけで判定するため､完全ではありません
+        /// 問題のありそうな文字があれば､それらを重複なしで出てきた順に返します(絵文字などが該当します)
+        pub fn can_convert(&self, kanji: &str) -> Result<(), Vec<char>> {
+            let mut bad = Vec::new();
+            for c in kanji.chars() {
+                if !Self::convertible(c) && !bad.contains(&c) {
+                    bad.push(c);
+                }
+            }
+            if bad.is_empty() {
+                Ok(())
+            } else {
+                Err(bad)
+            }
+        }
+
+        fn convertible(c: char) -> bool {
+            matches!(c,
+                '\t' | '\n' | '\r' | ' '..='~' // ASCII
+                | '°' | '±' | '×' | '÷'
+                | 'Α'..='ω' // ギリシャ文字
+                | 'Ё'..='ё' // キリル文字
+                | '‐'..='※' // 記号
+                | '←'..='⇔' // 矢印
+                | '─'..='╂' // 罫線
+                | '■'..='◯' // 図形
+                | '　'..='〜' // 和文の記号
+                | 'ぁ'..='ゞ' // ひらがな
+                | '゠'..='ヾ' // カタカナ
+                | '㐀'..='䶵' | '一'..='鿿' | '\u{F900}'..='\u{FAFF}' // 漢字
+                | '！'..='￥' // 全角英数字と半角カタカナ
+            )
+        }
+
+        /// convertでは長すぎて変換できない(エラーコード105になる)テキストを､分けて変換してから繋げます
+        /// テキストは。！？や改行の後で区切り､区切った文を長さの上限を超えない範囲でまとめて変換します
+        /// 1文だけで上限を超える場合は、の後､それもなければ文字の区切りで分けます
+        /// 変換結果がポーズの記号で終わっていない場合は、を挟んで繋げます
+        pub fn convert_long(&mut self, kanji: &str) -> Result<String, Box<dyn std::error::Error>> {
+            let mut result = String::new();
+            for chunk in split_text(kanji, MAX_INPUT_LEN) {
+                if chunk.trim().is_empty() {
+                    continue;
+                }
+                let koe = self.convert(chunk, None)?;
+                if koe.is_empty() {
+                    continue;
+                }
+                if !result.is_empty() && !result.ends_with(['、', '。', '？', '/']) {
+                    result.push('、');
+                }
+                result.push_str(&koe);
+            }
+            Ok(result)
+        }
+
+        /// pathのテキストファイル(UTF-8)を1行ずつconvertし､読みを行の順に返します｡字幕や台本をまとめて変換するのに使えます
+        /// 返すVecの要素はファイルの行と1対1に対応し､変換できた行はOk(読み)に､変換に失敗した行はErr(そのエラー)になります｡空行はOk(空文字列)になります
+        /// 1行の失敗で全体を止めずに最後の行まで変換するので､失敗した行だけを直して変換し直すのに使えます
+        /// ファイルを読めなかった場合は外側のErrを返します｡行末の\r\nは\nと同じように扱います
+        #[allow(clippy::type_complexity)]
+        pub fn convert_file<P: AsRef<Path>>(
+            &mut self,
+            path: P,
+        ) -> Result<Vec<Result<String, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>>
+        {
+            let text = fs::read_to_string(path)?;
+            let mut result = Vec::new();
+            for (i, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    result.push(Ok(String::new()));
+                    continue;
+                }
+                let koe = self.convert(line, None).map(|koe| koe.to_string());
+                if let Err(e) = &koe {
+                    warn_log!("{}行目を変換できませんでした: {}", i + 1, e);
+                }
+                result.push(koe);
+            }
+            Ok(result)
+        }
+
+        /// convertと同じですが､overridesで指定した単語はconvertせずに指定された読みに置き換えます
+        /// 入力テキストを上書き指定のある単語の前後で区切り､それ以外の部分をそれぞれconvertしてから/(アクセント句の区切り)で繋げます
+        /// 同じ位置から始まる単語が複数ある場合は長い方が優先され､読みは音声記号列として正しいかどうかを先に確かめます
+        pub fn convert_with_readings(
+            &mut self,
+            kanji: &str,
+            overrides: &[ReadingOverride],
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            for o in overrides {
+                phonetics::parse(o.kana)?;
+            }
+            let mut result = String::new();
+            let mut rest = kanji;
+            loop {
+                let found = overrides
+                    .iter()
+                    .filter(|o| !o.surface.is_empty())
+                    .filter_map(|o| rest.find(o.surface).map(|pos| (pos, o)))
+                    .min_by(|(pos, o), (pos2, o2)| {
+                        pos.cmp(pos2).then(o2.surface.len().cmp(&o.surface.len()))
+                    });
+                let (before, kana, after) = match found {
+                    Some((pos, o)) => (&rest[..pos], Some(o.kana), &rest[pos + o.surface.len()..]),
+                    None => (rest, None, ""),
+                };
+                if !before.is_empty() {
+                    Self::push_phrase(&mut result, &self.convert(before, None)?);
+                }
+                match kana {
+                    Some(k) => Self::push_phrase(&mut result, k),
+                    None => break,
+                }
+                rest = after;
+            }
+            Ok(result)
+        }
+
+        fn push_phrase(result: &mut String, phrase: &str) {
+            if phrase.is_empty() {
+                return;
+            }
+            if !result.is_empty() && !result.ends_with(['/', '、', '。', '？']) {
+                result.push('/');
+            }
+            result.push_str(phrase);
+        }
+
+        /// convertと同じですが､変換結果をbufferに書き込んで､その部分を文字列として返します
+        /// bufferは呼び出しごとに中身を消して使い回すので､同じbufferで何度も変換すればメモリの確保がほとんど起きません
+        /// バッファーの大きさと再試行の挙動､返すエラーはconvertにNoneを渡したときと同じです
+        pub fn convert_into<'b>(
+            &mut self,
+            kanji: &str,
+            buffer: &'b mut Vec<u8>,
+        ) -> Result<&'b str, Box<dyn std::error::Error>> {
+            let len = self.convert_raw(&CString::new(kanji)?, None, buffer)?;
+            buffer.truncate(len);
+            // convert_rawがUTF-8として正しいことを確かめてあります
+            Ok(std::str::from_utf8(buffer).unwrap())
+        }
+
         /// 本家のAqKanji2Koe_Convert_utf8に当たります
         /// 第一引数には漢字かな混じりのテキストを､第二引数はバッファーサイズで､基本的にはNoneを入れとけば公式推奨の入力テキストの２倍を確保しますが､心配性の方はSome(バイト単位のバッファーサイズ)を指定してください
+        /// 出力がバッファーに収まらなかった(出力がバッファーいっぱいまで書き込まれた)場合､set_convert_configで設定した回数だけバッファーを大きくして再試行し､それでも収まらなければAqK2KError::BufferTooSmallを返します
+        /// エラーコード105は入力テキストが長すぎることを表すので､再試行しません
+        /// バッファーはグローバルアロケーターで確保します｡バッファーを自分で用意したい(プールしたVecを使い回したいなど)場合はconvert_intoを使ってください
         pub fn convert<'b>(
             &mut self,
             kanji: &str,
             buffersize: Option<usize>,
         ) -> Result<AqK2Kstr<'b>, Box<dyn std::error::Error>> {
+            self.convert_cstr(&CString::new(kanji)?, buffersize)
+        }
+
+        /// convertと同じですが､テキストを&CStrで受け取ります
+        /// 同じテキストを何度も変換する場合に､CStringを作り直さずに使い回せます｡テキストはUTF-8にしてください
+        pub fn convert_cstr<'b>(
+            &mut self,
+            kanji: &CStr,
+            buffersize: Option<usize>,
+        ) -> Result<AqK2Kstr<'b>, Box<dyn std::error::Error>> {
+            let mut buffer = Vec::new();
+            let len = self.convert_raw(kanji, buffersize, &mut buffer)?;
+            // VecはLayout::array::<u8>(capacity)で確保されるので､同じLayoutでdeallocすれば開放できます
+            // convert_rawはbufferを1バイト以上の大きさにするので､capacityが0になる(確保されていない)ことはありません
+            let mut buffer = mem::ManuallyDrop::new(buffer);
+            let layout = alloc::Layout::array::<u8>(buffer.capacity())?;
+            let ptr = buffer.as_mut_ptr();
             unsafe {
-                let mut size: usize = match buffersize {
-                    Some(s) => s,
-                    None => (kanji.len() + 1) * 2,
+                Ok(AqK2Kstr {
+                    // convert_rawがUTF-8として正しいことを確かめてあります
+                    content: std::str::from_utf8_unchecked_mut(std::slice::from_raw_parts_mut(
+                        ptr, len,
+                    )),
+                    buffer: ptr,
+                    layout,
+                })
+            }
+        }
+
+        /// convert_cstrとconvert_intoで共通の､DLLを呼び出す部分です
+        /// bufferをbuffersize(Noneなら公式推奨の大きさ)にしてDLLに変換結果を書き込ませ､NULの前までの長さを返します
+        /// 出力がバッファーに収まらなかった場合や､末尾の文字が途中で切れている場合は､set_convert_configの設定に従ってバッファーを大きくして再試行します
+        /// 再試行しても収まらなかった場合はAqK2KError::BufferTooSmallを返します
+        fn convert_raw(
+            &mut self,
+            kanji: &CStr,
+            buffersize: Option<usize>,
+            buffer: &mut Vec<u8>,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
+            #[cfg(debug_assertions)]
+            let _guard = ConvertGuard::enter(&self.converting);
+            let mut size: usize = match buffersize {
+                Some(s) => s,
+                None => recommended_buffer_size(kanji.to_bytes().len())?,
+            };
+            // 大きさ0のバッファーはDLLに渡せないので､最低でも1バイトにします
+            size = size.max(self.config.min_buffer).max(1);
+            let mut retries = 0;
+            loop {
+                let size2: i32 = TryFrom::try_from(size)?;
+                // エラー時に途中までの出力を読み取れるように､ゼロで埋めておきます
+                buffer.clear();
+                buffer.resize(size, 0);
+                let start = Instant::now();
+                let errcode = unsafe {
+                    (self.dll.convert)(
+                        self.instance,
+                        kanji.as_ptr(),
+                        buffer.as_mut_ptr() as *mut c_char,
+                        size2,
+                    )
                 };
-                if size < 256 {
-                    size = 256;
-                }
-                let kanji2 = CString::new(kanji)?;
-                let layout = alloc::Layout::from_size_align_unchecked(
-                    mem::size_of::<c_char>() * size,
-                    mem::align_of::<c_char>(),
-                );
-                let buffer = alloc::alloc(layout) as *mut c_char;
-                let errcode = (self.dll.convert)(
-                    self.instance,
-                    kanji2.as_ptr(),
-                    buffer,
-                    TryFrom::try_from(size)?,
-                );
-                if errcode == 0 {
-                    Ok(AqK2Kstr {
-                        content: CStr::from_ptr(buffer).to_str()?.forcemut(),
-                        layout,
-                    })
-                } else {
-                    Err(Box::new(AqK2Kerr(errcode)))
+                debug_log!("AqKanji2Koe_Convert: {:?}", start.elapsed());
+                // NULが書き込まれていない場合でも､バッファーの外は読みません
+                let len = buffer.iter().position(|&b| b == 0).unwrap_or(size);
+                let overflow = errcode == 0 && len + 1 >= size;
+                // 末尾の文字がバッファーの境目で途中までしか書き込まれていない場合も､バッファーを大きくして再試行します
+                let truncated = errcode == 0
+                    && matches!(std::str::from_utf8(&buffer[..len]), Err(e) if e.error_len().is_none());
+                if (overflow || truncated) && retries < self.config.max_retries {
+                    size = size.saturating_mul(self.config.multiplier.max(2));
+                    retries += 1;
+                    continue;
+                }
+                if overflow {
+                    // 切れた出力を成功として返さないようにします
+                    warn_log!(
+                        "AqKanji2Koe_Convertの出力がバッファーに収まりませんでした: {}",
+                        size
+                    );
+                    return Err(Box::new(AqK2KError::BufferTooSmall {
+                        size,
+                        partial: String::from_utf8_lossy(&buffer[..len]).into_owned(),
+                    }));
                 }
+                if errcode != 0 {
+                    warn_log!("AqKanji2Koe_Convertがエラーを返しました: {}", errcode);
+                    let partial = String::from_utf8_lossy(&buffer[..len]).into_owned();
+                    return Err(Box::new(
+                        if (errcode == 105 || errcode == 107) && !partial.is_empty() {
+                            AqK2KError::Partial {
+                                code: errcode,
+                                partial,
+                            }
+                        } else {
+                            AqK2KError::Code(errcode)
+                        },
+                    ));
+                }
+                if std::str::from_utf8(&buffer[..len]).is_err() {
+                    return Err(Box::new(AqK2KError::InvalidUtf8Output(
+                        buffer[..len].to_vec(),
+                    )));
+                }
+                return Ok(len);
+            }
+        }
+
+        /// 短いテキストを1回変換して､DLL内部の初期化を済ませておきます
+        /// create直後の最初のconvertは辞書の読み込みなどで遅くなることがあるので､サーバーなどで最初のリクエストを待たせたくない場合はcreateの後に呼んでください
+        /// 初期化の時間は辞書や環境によりますが､その分だけ最初のconvertが速くなり､2回目以降のconvertと同じくらいの時間で済むようになります
+        pub fn warm_up(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            let start = Instant::now();
+            self.convert("あ", None)?;
+            debug_log!("warm_up: {:?}", start.elapsed());
+            Ok(())
+        }
+    }
+
+    /// convertに1回で渡すテキストの長さの目安(UTF-8でのバイト単位)
+    /// DLLが受け付ける長さの上限は公開されていないため､エラーコード105(入力テキストが長すぎる)を避けるためにこのクレートが使っている値です
+    /// convert_longはこの長さを超えないようにテキストを分けて変換します
+    pub const MAX_INPUT_LEN: usize = 1024;
+
+    /// 入力テキストの長さlen(バイト単位)から､公式推奨のバッファーサイズ((len + 1) * 2)を求めます
+    /// 桁あふれする場合は､DLLと同じくエラーコード105(入力テキストが長すぎる)にします
+    fn recommended_buffer_size(len: usize) -> Result<usize, AqK2KError> {
+        len.checked_add(1)
+            .and_then(|n| n.checked_mul(2))
+            .ok_or(AqK2KError::Code(105))
+    }
+
+    /// テキストをmaxバイト以下の部分に分けます｡文の区切りを優先し､次に、の後､最後は文字の区切りで分けます
+    fn split_text(text: &str, max: usize) -> Vec<&str> {
+        let mut result = Vec::new();
+        let mut rest = text;
+        while rest.len() > max {
+            let mut end = max;
+            while !rest.is_char_boundary(end) {
+                end -= 1;
             }
+            let head = &rest[..end];
+            let cut = [&['。', '！', '？', '!', '?', '\n'][..], &['、', ','][..]]
+                .iter()
+                .find_map(|seps| head.char_indices().rev().find(|(_, c)| seps.contains(c)))
+                .map(|(i, c)| i + c.len_utf8())
+                .unwrap_or(end)
+                .max(rest.chars().next().map_or(rest.len(), char::len_utf8));
+            result.push(&rest[..cut]);
+            rest = &rest[cut..];
+        }
+        if !rest.is_empty() {
+            result.push(rest);
         }
+        result
     }
 
     impl<'a> std::ops::Drop for AqK2Kinstance<'a> {
@@ -368,69 +2528,719 @@ pub mod aqkanji2koe {
 
     unsafe impl<'a> Sync for AqK2Kinstance<'a> {}
 
+    /// # 変換結果をキャッシュするAqK2Kinstanceのラッパー
+    /// UIの文字列のように同じテキストを何度も変換する場合に､2回目以降はDLLを呼ばずに前回の変換結果を返します
+    /// キャッシュはcapacity件までで､それを超えると最も長く使われていないものから捨てます
+    pub struct ReadingCache<'a> {
+        instance: AqK2Kinstance<'a>,
+        capacity: usize,
+        map: HashMap<String, String>,
+        // 使われた順に並べたキー｡先頭が最も長く使われていないものです
+        order: VecDeque<String>,
+    }
+
+    impl<'a> ReadingCache<'a> {
+        /// instanceをcapacity件までキャッシュするラッパーで包みます｡capacityが0の場合はキャッシュしません
+        pub fn new(instance: AqK2Kinstance<'a>, capacity: usize) -> Self {
+            ReadingCache {
+                instance,
+                capacity,
+                map: HashMap::new(),
+                order: VecDeque::new(),
+            }
+        }
+
+        /// convertと同じですが､キャッシュにあればDLLを呼ばずにそれを返します
+        pub fn convert_cached(&mut self, text: &str) -> Result<String, Box<dyn std::error::Error>> {
+            if let Some(reading) = self.map.get(text) {
+                let reading = reading.clone();
+                if let Some(i) = self.order.iter().position(|k| k == text) {
+                    let key = self.order.remove(i).unwrap();
+                    self.order.push_back(key);
+                }
+                return Ok(reading);
+            }
+            let reading = self.instance.convert(text, None)?.to_string();
+            if self.capacity > 0 {
+                if self.map.len() >= self.capacity {
+                    if let Some(old) = self.order.pop_front() {
+                        self.map.remove(&old);
+                    }
+                }
+                self.map.insert(text.to_string(), reading.clone());
+                self.order.push_back(text.to_string());
+            }
+            Ok(reading)
+        }
+
+        /// キャッシュされている件数を返します
+        pub fn len(&self) -> usize {
+            self.map.len()
+        }
+
+        /// キャッシュが空かどうかを返します
+        pub fn is_empty(&self) -> bool {
+            self.map.is_empty()
+        }
+
+        /// キャッシュを空にします｡ユーザ辞書を差し替えたときなどに使ってください
+        pub fn clear(&mut self) {
+            self.map.clear();
+            self.order.clear();
+        }
+
+        /// 包んでいるインスタンスを返します
+        pub fn instance(&mut self) -> &mut AqK2Kinstance<'a> {
+            &mut self.instance
+        }
+
+        /// キャッシュを捨てて､包んでいるインスタンスを取り出します
+        pub fn into_inner(self) -> AqK2Kinstance<'a> {
+            self.instance
+        }
+    }
+
     /// # convert関数で生成された文字列へのスマートポインタ
     /// このスマートポインタを参照外しすると変換された文字列が出てきます
     /// ヒープの開放はDrop時に実行されるため､自分で実行する必要はありません
     pub struct AqK2Kstr<'a> {
         content: &'a mut str,
+        // Vecで確保したバッファーの先頭です｡contentから求めずに､確保したときのポインタをそのまま開放します
+        buffer: *mut u8,
         layout: alloc::Layout,
     }
 
+    unsafe impl<'a> Send for AqK2Kstr<'a> {}
+
+    unsafe impl<'a> Sync for AqK2Kstr<'a> {}
+
     impl<'a> std::ops::Deref for AqK2Kstr<'a> {
-        type Target = &'a mut str;
+        type Target = str;
 
         fn deref(&self) -> &Self::Target {
-            &self.content
+            self.content
         }
     }
 
     impl<'a> std::ops::DerefMut for AqK2Kstr<'a> {
         fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.content
+            self.content
         }
     }
 
     impl<'a> std::ops::Drop for AqK2Kstr<'a> {
         fn drop(&mut self) {
             unsafe {
-                alloc::dealloc(&mut self.content.as_bytes_mut()[0] as *mut u8, self.layout);
+                // 変換結果が空文字列でもバッファーは確保されているので､必ず開放します
+                alloc::dealloc(self.buffer, self.layout);
             }
         }
     }
 
-    struct AqK2Kerr(i32);
+    /// # AqKanji2Koeのラッパーが返すエラー
+    /// Box<dyn std::error::Error>からdowncast_refで取り出せます
+    #[derive(Clone, PartialEq, Eq)]
+    pub enum AqK2KError {
+        /// DLLが返したエラーコードです
+        Code(i32),
+        /// DLLが出力した文字列がUTF-8として不正でした｡DLLが出力したバイト列をそのまま持っています
+        /// 文字コードの違うDLL(Shift_JIS版など)を読み込んでいる可能性があります
+        InvalidUtf8Output(Vec<u8>),
+        /// DLLがエラーコード105か107を返しましたが､途中までは変換されていました｡途中までの変換結果をpartialに持っています
+        /// デバッグや､途中までの結果で妥協する場合に使えます
+        Partial { code: i32, partial: String },
+        /// check_compatibilityで､辞書とDLLの組み合わせが正しくないことがわかりました｡DLLが返したエラーコード(200番台)を持っています
+        DictionaryMismatch(i32),
+        /// DLLにこの関数がないため使えません｡関数名を持っています
+        SymbolUnavailable(&'static str),
+        /// AqKanji2Koe.dllの代わりにAquesTalk.dllが指定されました
+        WrongDll,
+        /// 変換結果がバッファーに収まりませんでした｡最後に試したバッファーサイズ(バイト単位)と､収まった分の変換結果を持っています
+        /// AqK2KConvertConfigのmax_retriesを増やすか､convertに大きなバッファーサイズを指定してください
+        BufferTooSmall { size: usize, partial: String },
+    }
+
+    /// AqKanji2KoeのDLLが返すエラーコードと､そのメッセージの一覧です｡200番台と300番台はそれぞれ200と300で代表させています
+    const CODES: &[(i32, &str)] = &[
+        (100, "その他のエラー, エラーコード: 100"),
+        (
+            101,
+            "関数呼び出し時の引数がNULLになっている, エラーコード: 101",
+        ),
+        (
+            104,
+            "初期化されていない(初期化ルーチンが呼ばれていない), エラーコード: 104",
+        ),
+        (105, "入力テキストが長すぎる, エラーコード: 105"),
+        (
+            106,
+            "システム辞書データが指定されていない, エラーコード: 106",
+        ),
+        (
+            107,
+            "変換できない文字コードが含まれている, エラーコード: 107",
+        ),
+        (200, "システム辞書(aqdic.bin)が不正, エラーコード: 200番台"),
+        (300, "ユーザ辞書(aq_user.dic)が不正, エラーコード: 300番台"),
+    ];
+
+    /// CODESの英語版です
+    const CODES_EN: &[(i32, &str)] = &[
+        (100, "Other error, error code: 100"),
+        (101, "NULL argument passed to the function, error code: 101"),
+        (
+            104,
+            "Not initialized (initialization routine not called), error code: 104",
+        ),
+        (105, "Input text too long, error code: 105"),
+        (106, "System dictionary data not specified, error code: 106"),
+        (
+            107,
+            "Input contains characters that cannot be converted, error code: 107",
+        ),
+        (
+            200,
+            "Invalid system dictionary (aqdic.bin), error code: 2xx",
+        ),
+        (
+            300,
+            "Invalid user dictionary (aq_user.dic), error code: 3xx",
+        ),
+    ];
+
+    impl AqK2KError {
+        /// Displayで表示されるメッセージ(日本語)の英語版を返します
+        pub fn message_en(&self) -> &str {
+            match self {
+                AqK2KError::Code(code) | AqK2KError::Partial { code, .. } => {
+                    let code = match code {
+                        200..=299 => 200,
+                        300..=399 => 300,
+                        c => *c,
+                    };
+                    CODES_EN
+                        .iter()
+                        .find(|(c, _)| *c == code)
+                        .map_or("Undefined error", |(_, m)| m)
+                }
+                AqK2KError::InvalidUtf8Output(_) => "DLL output is not valid UTF-8",
+                AqK2KError::DictionaryMismatch(_) => {
+                    "Dictionary and DLL versions do not match (use the dictionary from the same distribution as the DLL)"
+                }
+                AqK2KError::SymbolUnavailable(_) => "The DLL does not export this function",
+                AqK2KError::WrongDll => {
+                    "AquesTalk.dll was given instead of AqKanji2Koe.dll (load it with AqDLL::load)"
+                }
+                AqK2KError::BufferTooSmall { .. } => "The output did not fit in the buffer",
+            }
+        }
+
+        /// DLLが返すエラーコードと､そのメッセージの一覧を返します
+        /// 200番台(システム辞書の不正)と300番台(ユーザ辞書の不正)は､それぞれ200と300の1件ずつにまとめています
+        pub fn all_codes() -> &'static [(i32, &'static str)] {
+            CODES
+        }
+
+        /// DLLが返したエラーコードをそのまま返します(200番台や300番台も元の値のまま返します)｡DLLのエラーコードでないエラーの場合はNoneを返します
+        pub fn code(&self) -> Option<i32> {
+            match self {
+                AqK2KError::Code(code)
+                | AqK2KError::Partial { code, .. }
+                | AqK2KError::DictionaryMismatch(code) => Some(*code),
+                _ => None,
+            }
+        }
 
-    impl AqK2Kerr {
         fn msg(&self) -> &str {
-            match self.0 {
-                100 => "その他のエラー, エラーコード: 100",
-                101 => "関数呼び出し時の引数がNULLになっている, エラーコード: 101",
-                104 => "初期化されていない(初期化ルーチンが呼ばれていない), エラーコード: 104",
-                105 => "入力テキストが長すぎる, エラーコード: 105",
-                106 => "システム辞書データが指定されていない, エラーコード: 106",
-                107 => "変換できない文字コードが含まれている, エラーコード: 107",
-                200..=299 => "システム辞書(aqdic.bin)が不正, エラーコード: 200番台",
-                300..=399 => "ユーザ辞書(aq_user.dic)が不正, エラーコード: 300番台",
-                _ => "未定義のエラー",
+            match self {
+                AqK2KError::Code(code) | AqK2KError::Partial { code, .. } => {
+                    let code = match code {
+                        200..=299 => 200,
+                        300..=399 => 300,
+                        c => *c,
+                    };
+                    CODES
+                        .iter()
+                        .find(|(c, _)| *c == code)
+                        .map_or("未定義のエラー", |(_, m)| m)
+                }
+                AqK2KError::InvalidUtf8Output(_) => "DLLの出力がUTF-8として不正",
+                AqK2KError::DictionaryMismatch(_) => {
+                    "辞書とDLLのバージョンが合っていない(辞書とDLLは同じ配布物のものを使ってください)"
+                }
+                AqK2KError::SymbolUnavailable(_) => "DLLにこの関数がない",
+                AqK2KError::WrongDll => {
+                    "AqKanji2Koe.dllではなくAquesTalk.dllが指定されている(AqDLL::loadで読み込んでください)"
+                }
+                AqK2KError::BufferTooSmall { .. } => "変換結果がバッファーに収まらなかった",
             }
         }
     }
 
-    impl std::fmt::Display for AqK2Kerr {
+    impl std::fmt::Display for AqK2KError {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.msg())
+            match self {
+                AqK2KError::InvalidUtf8Output(bytes) => {
+                    write!(
+                        f,
+                        "{}, 出力: {}",
+                        self.msg(),
+                        String::from_utf8_lossy(bytes)
+                    )
+                }
+                AqK2KError::Partial { partial, .. } => {
+                    write!(f, "{}, 途中までの変換結果: {}", self.msg(), partial)
+                }
+                AqK2KError::DictionaryMismatch(code) => {
+                    write!(f, "{}, エラーコード: {}", self.msg(), code)
+                }
+                AqK2KError::SymbolUnavailable(symbol) => {
+                    write!(f, "{}, 関数名: {}", self.msg(), symbol)
+                }
+                AqK2KError::BufferTooSmall { size, .. } => {
+                    write!(f, "{}, バッファーサイズ: {}", self.msg(), size)
+                }
+                _ => write!(f, "{}", self.msg()),
+            }
         }
     }
 
-    impl std::fmt::Debug for AqK2Kerr {
+    impl std::fmt::Debug for AqK2KError {
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.msg())
+            write!(f, "{}", self)
         }
     }
 
-    impl std::error::Error for AqK2Kerr {
+    impl std::error::Error for AqK2KError {
         fn description(&self) -> &str {
             self.msg()
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::mock::{self, MockLib};
+
+        #[test]
+        fn mock_convert() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = dll.create("dic").unwrap();
+            assert_eq!(
+                &*instance.convert("こんにちは", None).unwrap(),
+                "こんにちは"
+            );
+            let e = dll.create("err:101").err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(AqK2KError::Code(101))
+            ));
+        }
+
+        /// min_bufferを1にして､convertにSome(size)で渡したバッファーサイズをそのまま使うようにしたインスタンスを作ります
+        fn tiny_buffer(dll: &AqK2KDLL, max_retries: u32) -> AqK2Kinstance<'static> {
+            let mut instance = dll.create("dic").unwrap();
+            instance.set_convert_config(AqK2KConvertConfig {
+                min_buffer: 1,
+                max_retries,
+                multiplier: 2,
+            });
+            instance
+        }
+
+        #[test]
+        fn convert_full_buffer_is_error() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = tiny_buffer(&dll, 0);
+            mock::take_convert_sizes();
+            let e = instance.convert("こんにちは", Some(4)).err().unwrap();
+            assert_eq!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(&AqK2KError::BufferTooSmall {
+                    size: 4,
+                    partial: "こ".to_string()
+                })
+            );
+            assert_eq!(mock::take_convert_sizes(), vec![4]);
+        }
+
+        #[test]
+        fn convert_retries_until_fit() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = tiny_buffer(&dll, 3);
+            mock::take_convert_sizes();
+            // 15バイトの出力は､NULを含めて32バイトのバッファーで初めて収まります
+            assert_eq!(
+                &*instance.convert("こんにちは", Some(4)).unwrap(),
+                "こんにちは"
+            );
+            assert_eq!(mock::take_convert_sizes(), vec![4, 8, 16, 32]);
+
+            let mut instance = tiny_buffer(&dll, 2);
+            let e = instance.convert("こんにちは", Some(4)).err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(AqK2KError::BufferTooSmall { size: 16, .. })
+            ));
+            assert_eq!(mock::take_convert_sizes(), vec![4, 8, 16]);
+        }
+
+        #[test]
+        fn convert_does_not_retry_on_105() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = tiny_buffer(&dll, 3);
+            mock::take_convert_sizes();
+            mock::push_convert(mock::ConvertResult::Error(105, Vec::new()));
+            let e = instance.convert("こんにちは", Some(64)).err().unwrap();
+            assert_eq!(e.downcast_ref::<AqK2KError>(), Some(&AqK2KError::Code(105)));
+            assert_eq!(mock::take_convert_sizes(), vec![64]);
+        }
+
+        #[test]
+        fn convert_invalid_utf8() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = dll.create("dic").unwrap();
+            mock::push_convert(mock::ConvertResult::Output(b"a\xff\xfeb".to_vec()));
+            let e = instance.convert("あ", None).err().unwrap();
+            assert_eq!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(&AqK2KError::InvalidUtf8Output(b"a\xff\xfeb".to_vec()))
+            );
+            // convert_intoでも同じエラーになり､バッファーの中身は返しません
+            mock::push_convert(mock::ConvertResult::Output(vec![0x82, 0xa0]));
+            let mut buffer = Vec::new();
+            let e = instance.convert_into("あ", &mut buffer).err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(AqK2KError::InvalidUtf8Output(_))
+            ));
+        }
+
+        #[test]
+        fn can_convert_emoji_and_non_bmp() {
+            let dll = mock::aqkanji2koe();
+            let instance = dll.create("dic").unwrap();
+            assert_eq!(instance.can_convert("漢字とかなとカナとABC"), Ok(()));
+            // 同じ文字は1回だけ返します
+            assert_eq!(
+                instance.can_convert("こんにちは😀𠮷😀"),
+                Err(vec!['😀', '𠮷'])
+            );
+            // 異体字セレクタやゼロ幅接合子も変換できません
+            assert_eq!(
+                instance.can_convert("❤\u{FE0F}👨\u{200D}👩"),
+                Err(vec!['❤', '\u{FE0F}', '👨', '\u{200D}', '👩'])
+            );
+        }
+
+        #[test]
+        fn wrong_dll() {
+            let e = unsafe { AqK2KDLL::from_source(MockLib::aquestalk(), None, None) }
+                .err()
+                .unwrap();
+            assert_eq!(e.downcast_ref::<AqK2KError>(), Some(&AqK2KError::WrongDll));
+        }
+
+        #[test]
+        fn error_code_table() {
+            let table = [
+                (AqK2KError::Code(101), Some(101)),
+                (AqK2KError::Code(205), Some(205)),
+                (
+                    AqK2KError::Partial {
+                        code: 107,
+                        partial: "あ".to_string(),
+                    },
+                    Some(107),
+                ),
+                (AqK2KError::DictionaryMismatch(201), Some(201)),
+                (AqK2KError::InvalidUtf8Output(vec![0xff]), None),
+                (
+                    AqK2KError::SymbolUnavailable("AqKanji2Koe_Create_Ptr"),
+                    None,
+                ),
+                (AqK2KError::WrongDll, None),
+                (
+                    AqK2KError::BufferTooSmall {
+                        size: 4,
+                        partial: String::new(),
+                    },
+                    None,
+                ),
+            ];
+            for (e, code) in table.iter() {
+                assert_eq!(e.code(), *code, "{}", e);
+            }
+        }
+
+        #[test]
+        fn all_codes_round_trip() {
+            for (code, _) in AqK2KError::all_codes() {
+                assert_eq!(AqK2KError::Code(*code).code(), Some(*code));
+            }
+        }
+
+        #[test]
+        fn all_codes_have_messages() {
+            for (code, message) in AqK2KError::all_codes() {
+                let e = AqK2KError::Code(*code);
+                assert!(!message.is_empty());
+                assert_eq!(e.to_string(), *message);
+                assert!(!e.message_en().is_empty());
+                assert_ne!(e.message_en(), "Undefined error", "{}", code);
+            }
+        }
+
+        #[test]
+        fn drop_empty_str() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = dll.create("dic").unwrap();
+            mock::push_convert(mock::ConvertResult::Output(Vec::new()));
+            let koe = instance.convert("あ", None).unwrap();
+            assert!(koe.is_empty());
+            drop(koe);
+        }
+
+        /// textをキャッシュ経由で変換し､DLLを呼んだ回数を返します
+        fn cached_calls(cache: &mut ReadingCache, text: &str) -> usize {
+            mock::take_convert_sizes();
+            assert_eq!(cache.convert_cached(text).unwrap(), text);
+            mock::take_convert_sizes().len()
+        }
+
+        #[test]
+        fn reading_cache_evicts_least_recently_used() {
+            let dll = mock::aqkanji2koe();
+            let mut cache = ReadingCache::new(dll.create("dic").unwrap(), 2);
+            assert_eq!(cached_calls(&mut cache, "あ"), 1);
+            assert_eq!(cached_calls(&mut cache, "い"), 1);
+            assert_eq!(cached_calls(&mut cache, "う"), 1);
+            assert_eq!(cache.len(), 2);
+            // 最も長く使われていない「あ」が追い出されています
+            assert_eq!(cached_calls(&mut cache, "う"), 0);
+            assert_eq!(cached_calls(&mut cache, "あ"), 1);
+        }
+
+        #[test]
+        fn reading_cache_refreshes_on_hit() {
+            let dll = mock::aqkanji2koe();
+            let mut cache = ReadingCache::new(dll.create("dic").unwrap(), 2);
+            cached_calls(&mut cache, "あ");
+            cached_calls(&mut cache, "い");
+            // 「あ」を読んだので､次に追い出されるのは「い」です
+            assert_eq!(cached_calls(&mut cache, "あ"), 0);
+            assert_eq!(cached_calls(&mut cache, "う"), 1);
+            assert_eq!(cached_calls(&mut cache, "あ"), 0);
+            assert_eq!(cached_calls(&mut cache, "い"), 1);
+        }
+
+        #[test]
+        fn reading_cache_capacity_zero() {
+            let dll = mock::aqkanji2koe();
+            let mut cache = ReadingCache::new(dll.create("dic").unwrap(), 0);
+            assert_eq!(cached_calls(&mut cache, "あ"), 1);
+            assert_eq!(cached_calls(&mut cache, "あ"), 1);
+            assert!(cache.is_empty());
+        }
+
+        #[test]
+        fn split_text_at_max_input_len() {
+            let exact = "a".repeat(MAX_INPUT_LEN);
+            assert_eq!(split_text(&exact, MAX_INPUT_LEN), vec![exact.as_str()]);
+            let over = "a".repeat(MAX_INPUT_LEN + 1);
+            assert_eq!(
+                split_text(&over, MAX_INPUT_LEN),
+                vec![&over[..MAX_INPUT_LEN], "a"]
+            );
+            // 3バイトの「あ」がMAX_INPUT_LENをまたぐ場合は､「あ」の前で切ります
+            let straddle = format!("{}あ", "a".repeat(MAX_INPUT_LEN - 1));
+            let parts = split_text(&straddle, MAX_INPUT_LEN);
+            assert_eq!(parts, vec![&straddle[..MAX_INPUT_LEN - 1], "あ"]);
+            assert!(parts.iter().all(|p| p.len() <= MAX_INPUT_LEN));
+        }
+
+        #[test]
+        fn check_compatibility_mismatch() {
+            let dll = mock::aqkanji2koe();
+            let error = |r: Result<(), Box<dyn std::error::Error>>| {
+                r.err().unwrap().downcast_ref::<AqK2KError>().cloned()
+            };
+            assert!(dll.check_compatibility("dic").is_ok());
+            // 偽のcreateは"err:"の後ろの数字をエラーコードにします
+            assert_eq!(
+                error(dll.check_compatibility("err:201")),
+                Some(AqK2KError::DictionaryMismatch(201))
+            );
+            mock::push_convert(mock::ConvertResult::Error(203, Vec::new()));
+            assert_eq!(
+                error(dll.check_compatibility("dic")),
+                Some(AqK2KError::DictionaryMismatch(203))
+            );
+            // 200番台以外のエラーはそのまま返します
+            assert_eq!(
+                error(dll.check_compatibility("err:101")),
+                Some(AqK2KError::Code(101))
+            );
+        }
+
+        #[test]
+        fn dev_key_from_env() {
+            let load = |builder: AqK2KDLLBuilder| unsafe {
+                AqK2KDLL::from_source(MockLib::aqkanji2koe(), None, builder.devkey.as_deref())
+                    .unwrap()
+            };
+            mock::take_devkeys();
+            env::set_var(DEVKEY_ENV, "env-key");
+            let builder = AqK2KDLL::builder("AqKanji2Koe").dev_key_from_env();
+            env::remove_var(DEVKEY_ENV);
+            load(builder);
+            assert_eq!(mock::take_devkeys(), vec!["env-key"]);
+            // 環境変数がなければ制限解除をしません
+            load(AqK2KDLL::builder("AqKanji2Koe").dev_key_from_env());
+            assert!(mock::take_devkeys().is_empty());
+            load(AqK2KDLL::builder("AqKanji2Koe").dev_key("key"));
+            assert_eq!(mock::take_devkeys(), vec!["key"]);
+        }
+
+        #[test]
+        fn missing_create_ptr() {
+            let lib = MockLib::aqkanji2koe().without("AqKanji2Koe_Create_Ptr");
+            let dll = unsafe { AqK2KDLL::from_source(lib, None, None) }.unwrap();
+            let e = dll.create_from_static(b"dic", None).err().unwrap();
+            assert_eq!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(&AqK2KError::SymbolUnavailable("AqKanji2Koe_Create_Ptr"))
+            );
+            // createは今までどおり使えます
+            assert!(dll.create("dic").is_ok());
+        }
+
+        #[test]
+        fn drop_str_after_mutation() {
+            let dll = mock::aqkanji2koe();
+            let mut instance = dll.create("dic").unwrap();
+            // 空の入力は空の変換結果になります
+            let empty = instance.convert("", None).unwrap();
+            assert_eq!(&*empty, "");
+            drop(empty);
+            let mut koe = instance.convert("abc", None).unwrap();
+            koe.make_ascii_uppercase();
+            assert_eq!(&*koe, "ABC");
+            drop(koe);
+        }
+
+        #[test]
+        fn convert_retries_truncated_character() {
+            let dll = mock::aqkanji2koe();
+            let truncated = || mock::ConvertResult::Output(b"\xe3\x81\x82\xe3\x81".to_vec());
+            let mut instance = tiny_buffer(&dll, 1);
+            mock::take_convert_sizes();
+            mock::push_convert(truncated());
+            // 2回目は入力をそのまま出力します
+            assert_eq!(&*instance.convert("あい", Some(64)).unwrap(), "あい");
+            assert_eq!(mock::take_convert_sizes(), vec![64, 128]);
+
+            let mut instance = tiny_buffer(&dll, 0);
+            mock::push_convert(truncated());
+            let e = instance.convert("あい", Some(64)).err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(AqK2KError::InvalidUtf8Output(_))
+            ));
+            // バッファーいっぱいで切れた場合は､収まらなかったことを返します
+            let e = instance.convert("あい", Some(5)).err().unwrap();
+            assert!(matches!(
+                e.downcast_ref::<AqK2KError>(),
+                Some(AqK2KError::BufferTooSmall { size: 5, .. })
+            ));
+        }
+
+        #[test]
+        fn clones_create_instances() {
+            let dll = mock::aqkanji2koe();
+            let handles: Vec<_> = ["あ", "い", "う"]
+                .iter()
+                .map(|text| {
+                    let dll = dll.clone();
+                    std::thread::spawn(move || {
+                        let mut instance = dll.create("dic").unwrap();
+                        instance.convert(text, None).unwrap().to_string()
+                    })
+                })
+                .collect();
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            assert_eq!(results, vec!["あ", "い", "う"]);
+            // クローンとインスタンスは全てDropされ､DLLを持っているのはdllだけです
+            assert_eq!(Arc::strong_count(&dll.dll), 1);
+        }
+
+        #[test]
+        fn recommended_buffer_size_overflow() {
+            assert_eq!(recommended_buffer_size(0), Ok(2));
+            assert_eq!(
+                recommended_buffer_size(usize::MAX / 2 - 1),
+                Ok(usize::MAX - 1)
+            );
+            for len in [
+                usize::MAX / 2,
+                usize::MAX / 2 + 1,
+                usize::MAX - 1,
+                usize::MAX,
+            ]
+            .iter()
+            {
+                assert_eq!(recommended_buffer_size(*len), Err(AqK2KError::Code(105)));
+            }
+        }
+
+        #[test]
+        fn create_from_static_passes_slices() {
+            static SYS: [u8; 16] = [0; 16];
+            static USER: [u8; 4] = [0; 4];
+            let dll = mock::aqkanji2koe();
+            mock::take_create_ptr_args();
+            let instance = dll.create_from_static(&SYS, None).unwrap();
+            assert_eq!(instance.dict_memory_estimate(), Some(16));
+            let instance2 = dll.create_from_static(&SYS, Some(&USER)).unwrap();
+            assert_eq!(instance2.dict_memory_estimate(), Some(16));
+            let sys = SYS.as_ptr() as usize;
+            assert_eq!(
+                mock::take_create_ptr_args(),
+                vec![(sys, 0), (sys, USER.as_ptr() as usize)]
+            );
+        }
+
+        #[test]
+        fn diff_phrases_positions() {
+            let diff = |a: &str, b: &str| diff_phrases(a.to_string(), b.to_string());
+            let pair = |a: &str, b: &str| ReadingDiff {
+                system: a.to_string(),
+                user: b.to_string(),
+            };
+            // 片方が空の場合
+            assert!(diff("", "").is_empty());
+            assert_eq!(diff("", "あ"), vec![pair("", "あ")]);
+            assert_eq!(diff("あ/い", ""), vec![pair("あ/い", "")]);
+            // 同じ読み
+            assert!(diff("あ/い、う", "あ/い、う").is_empty());
+            // 先頭と末尾の区切りだけが違う場合
+            assert_eq!(diff("あ/い/う", "か/い/う"), vec![pair("あ", "か")]);
+            assert_eq!(diff("あ/い/う", "あ/い/く"), vec![pair("う", "く")]);
+            // 区切りの数が違う場合は全体を返します
+            assert_eq!(diff("あ/い", "あい"), vec![pair("あ/い", "あい")]);
+        }
+
+        #[test]
+        fn split_text_boundaries() {
+            assert!(split_text("", 10).is_empty());
+            assert_eq!(split_text("あいう", 9), vec!["あいう"]);
+            // 文末の区切りを読点より優先します
+            assert_eq!(split_text("あ、い。うえお", 18), vec!["あ、い。", "うえお"]);
+            assert_eq!(split_text("あい、うえお", 12), vec!["あい、", "うえお"]);
+            // 区切りがない場合は文字の境目で切ります
+            assert_eq!(split_text("あいうえ", 7), vec!["あい", "うえ"]);
+            // maxが1文字より短くても､1文字ずつ進みます
+            assert_eq!(split_text("あい", 2), vec!["あ", "い"]);
+        }
+    }
 }
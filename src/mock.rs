@@ -0,0 +1,263 @@
+//! テストでDLLの代わりに使う偽のAquesTalkとAqKanji2Koeです
+//! 本物のDLLがなくても､DLLを呼ぶ部分のテストができるようにします
+//! 偽のAquesTalk_Synthe_Utf8は､音声記号1文字につき100msの無音のWAVデータ(8000Hz､モノラル､16bit)を返します
+//! 偽のAqKanji2Koe_Convertは､入力をそのまま出力に書き込みます
+use crate::{
+    aqkanji2koe::AqK2KDLL,
+    aquestalk1::AqDLL,
+    wav::{self, PcmSpec},
+    SymbolSource,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    ffi::{c_void, CStr},
+    mem,
+    os::raw::c_char,
+    ptr, slice,
+    time::Duration,
+};
+
+/// # 偽の関数をエクスポートする偽のDLL
+pub(crate) struct MockLib(Vec<(&'static str, *const ())>);
+
+impl MockLib {
+    /// AquesTalkの関数をエクスポートする偽のDLLを作ります
+    pub(crate) fn aquestalk() -> Self {
+        MockLib(vec![
+            ("AquesTalk_Synthe_Utf8", synthe as *const ()),
+            ("AquesTalk_FreeWave", freewav as *const ()),
+        ])
+    }
+
+    /// AqKanji2Koeの関数をエクスポートする偽のDLLを作ります
+    /// 変換の関数はWindows版とそれ以外の名前の両方でエクスポートします
+    pub(crate) fn aqkanji2koe() -> Self {
+        MockLib(vec![
+            ("AqKanji2Koe_Create", create as *const ()),
+            ("AqKanji2Koe_Create_Ptr", create_ptr as *const ()),
+            ("AqKanji2Koe_Release", release as *const ()),
+            ("AqKanji2Koe_Convert", convert as *const ()),
+            ("AqKanji2Koe_Convert_utf8", convert as *const ()),
+            ("AqKanji2Koe_SetDevKey", setdevkey as *const ()),
+        ])
+    }
+
+    /// nameの関数をエクスポートしないようにします
+    pub(crate) fn without(mut self, name: &str) -> Self {
+        self.0.retain(|(n, _)| *n != name);
+        self
+    }
+}
+
+impl SymbolSource for MockLib {
+    unsafe fn symbol<T: Copy>(&self, name: &str) -> Result<T, libloading::Error> {
+        assert_eq!(mem::size_of::<T>(), mem::size_of::<*const ()>());
+        self.0
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, f)| mem::transmute_copy(f))
+            .ok_or(libloading::Error::DlSymUnknown)
+    }
+
+    fn into_library(self) -> Option<libloading::Library> {
+        None
+    }
+}
+
+/// 偽のAquesTalkを読み込んだAqDLLを作ります
+pub(crate) fn aquestalk() -> AqDLL<'static> {
+    unsafe { AqDLL::from_source(MockLib::aquestalk()).unwrap() }
+}
+
+/// 偽のAqKanji2Koeを読み込んだAqK2KDLLを作ります
+pub(crate) fn aqkanji2koe() -> AqK2KDLL<'static> {
+    unsafe { AqK2KDLL::from_source(MockLib::aqkanji2koe(), None, None).unwrap() }
+}
+
+/// 音声記号1文字あたりの音声の長さ
+pub(crate) const CHAR_DURATION: Duration = Duration::from_millis(100);
+
+/// 偽のWAVデータの形式
+pub(crate) const SPEC: PcmSpec = PcmSpec {
+    sample_rate: 8000,
+    channels: 1,
+    bits_per_sample: 16,
+};
+
+/// 先頭に長さを書いたバッファーを確保してdataを書き込み､長さの後ろのポインタを返します
+/// freewavで開放できます
+fn alloc(data: &[u8]) -> *mut u8 {
+    let mut buf = (data.len() as u64).to_ne_bytes().to_vec();
+    buf.extend_from_slice(data);
+    let buf = Box::into_raw(buf.into_boxed_slice()) as *mut u8;
+    unsafe { buf.add(mem::size_of::<u64>()) }
+}
+
+/// # 偽のAquesTalk_Synthe_Utf8が返す結果
+pub(crate) enum SyntheResult {
+    /// nullとこのエラーコードを返します
+    Error(i32),
+    /// WAVデータの代わりにこのバイト列を返します
+    Data(Vec<u8>),
+    /// 空のバッファーを返し､長さとしてこの値を返します
+    Size(i32),
+}
+
+thread_local! {
+    static SYNTHE_RESULTS: RefCell<VecDeque<SyntheResult>> = const { RefCell::new(VecDeque::new()) };
+    static SYNTHE_CALLS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static FREED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// このスレッドで次に呼ばれる偽のAquesTalk_Synthe_Utf8の結果を設定します｡何回か呼ぶと､呼ばれる順に使います
+/// 設定した結果を使い切った後は､音声記号列の長さに合わせた無音のWAVデータを返します
+pub(crate) fn push_synthe(result: SyntheResult) {
+    SYNTHE_RESULTS.with(|r| r.borrow_mut().push_back(result));
+}
+
+/// このスレッドで偽のAquesTalk_Synthe_Utf8に渡された音声記号列を､呼ばれた順に返して記録を消します
+/// 前のテストの記録を消すために､テストの始めにも呼んでください
+pub(crate) fn take_synthe_calls() -> Vec<String> {
+    SYNTHE_CALLS.with(|c| c.take())
+}
+
+/// このスレッドで偽のAquesTalk_FreeWaveが呼ばれた回数を返して､0に戻します
+/// 前のテストの記録を消すために､テストの始めにも呼んでください
+pub(crate) fn take_freed() -> usize {
+    FREED.with(|f| f.replace(0))
+}
+
+unsafe extern "system" fn synthe(koe: *const c_char, _speed: i32, size: *mut i32) -> *mut u8 {
+    let koe = CStr::from_ptr(koe).to_string_lossy();
+    SYNTHE_CALLS.with(|c| c.borrow_mut().push(koe.to_string()));
+    let wav = match SYNTHE_RESULTS.with(|r| r.borrow_mut().pop_front()) {
+        Some(SyntheResult::Error(code)) => {
+            *size = code;
+            return ptr::null_mut();
+        }
+        Some(SyntheResult::Data(data)) => data,
+        Some(SyntheResult::Size(len)) => {
+            *size = len;
+            return alloc(&[]);
+        }
+        None => {
+            let chars = koe
+                .chars()
+                .filter(|c| !matches!(c, '\'' | '/' | '_'))
+                .count();
+            wav::silence_wav(SPEC, CHAR_DURATION * chars as u32).unwrap()
+        }
+    };
+    *size = wav.len() as i32;
+    alloc(&wav)
+}
+
+unsafe extern "system" fn freewav(wav: *mut u8) {
+    FREED.with(|f| f.set(f.get() + 1));
+    let start = wav.sub(mem::size_of::<u64>());
+    let mut len = [0; 8];
+    len.copy_from_slice(slice::from_raw_parts(start, 8));
+    let len = u64::from_ne_bytes(len) as usize + mem::size_of::<u64>();
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(start, len)));
+}
+
+/// 偽のインスタンスを作ります｡インスタンスごとに違うアドレスになるように1バイト確保します
+fn new_instance() -> *mut c_void {
+    Box::into_raw(Box::new(0u8)) as *mut c_void
+}
+
+/// pathdicが"err:"で始まる場合は､その後ろの数字をエラーコードにしてnullを返します
+unsafe extern "system-unwind" fn create(pathdic: *const c_char, errcode: *mut i32) -> *mut c_void {
+    let pathdic = CStr::from_ptr(pathdic).to_string_lossy();
+    match pathdic.strip_prefix("err:") {
+        Some(code) => {
+            *errcode = code.parse().unwrap();
+            ptr::null_mut()
+        }
+        None => new_instance(),
+    }
+}
+
+thread_local! {
+    static CREATE_PTR_ARGS: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// このスレッドで偽のAqKanji2Koe_Create_Ptrに渡されたシステム辞書とユーザ辞書のアドレスを､呼ばれた順に返して記録を消します
+/// 前のテストの記録を消すために､テストの始めにも呼んでください
+pub(crate) fn take_create_ptr_args() -> Vec<(usize, usize)> {
+    CREATE_PTR_ARGS.with(|a| a.take())
+}
+
+unsafe extern "system-unwind" fn create_ptr(
+    sysdic: *const c_void,
+    userdic: *const c_void,
+    _errcode: *mut i32,
+) -> *mut c_void {
+    CREATE_PTR_ARGS.with(|a| a.borrow_mut().push((sysdic as usize, userdic as usize)));
+    new_instance()
+}
+
+unsafe extern "system-unwind" fn release(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut u8));
+}
+
+/// # 偽のAqKanji2Koe_Convertが返す結果
+pub(crate) enum ConvertResult {
+    /// 入力の代わりにこのバイト列を出力します
+    Output(Vec<u8>),
+    /// エラーコードを返します｡途中までの出力としてバイト列を書き込みます
+    Error(i32, Vec<u8>),
+}
+
+thread_local! {
+    static CONVERT_RESULTS: RefCell<VecDeque<ConvertResult>> = const { RefCell::new(VecDeque::new()) };
+    static CONVERT_SIZES: RefCell<Vec<i32>> = const { RefCell::new(Vec::new()) };
+}
+
+/// このスレッドで次に呼ばれる偽のAqKanji2Koe_Convertの結果を設定します｡何回か呼ぶと､呼ばれる順に使います
+/// 設定した結果を使い切った後は､入力をそのまま出力します
+pub(crate) fn push_convert(result: ConvertResult) {
+    CONVERT_RESULTS.with(|r| r.borrow_mut().push_back(result));
+}
+
+/// このスレッドで偽のAqKanji2Koe_Convertに渡されたバッファーサイズを､呼ばれた順に返して記録を消します
+/// 前のテストの記録を消すために､テストの始めにも呼んでください
+pub(crate) fn take_convert_sizes() -> Vec<i32> {
+    CONVERT_SIZES.with(|s| s.take())
+}
+
+/// 本物と同じように､バッファーに入りきらない分は切り捨てて書き込みます
+unsafe extern "system-unwind" fn convert(
+    _instance: *mut c_void,
+    kanji: *const c_char,
+    koe: *mut c_char,
+    size: i32,
+) -> i32 {
+    CONVERT_SIZES.with(|s| s.borrow_mut().push(size));
+    let (code, output) = match CONVERT_RESULTS.with(|r| r.borrow_mut().pop_front()) {
+        Some(ConvertResult::Output(output)) => (0, output),
+        Some(ConvertResult::Error(code, output)) => (code, output),
+        None => (0, CStr::from_ptr(kanji).to_bytes().to_vec()),
+    };
+    let len = output.len().min(size as usize - 1);
+    ptr::copy_nonoverlapping(output.as_ptr(), koe as *mut u8, len);
+    *koe.add(len) = 0;
+    code
+}
+
+thread_local! {
+    static DEVKEYS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// このスレッドで偽のAqKanji2Koe_SetDevKeyに渡されたライセンスキーを､呼ばれた順に返して記録を消します
+/// 前のテストの記録を消すために､テストの始めにも呼んでください
+pub(crate) fn take_devkeys() -> Vec<String> {
+    DEVKEYS.with(|k| k.take())
+}
+
+unsafe extern "system-unwind" fn setdevkey(key: *const c_char) -> i32 {
+    let key = CStr::from_ptr(key).to_string_lossy().into_owned();
+    DEVKEYS.with(|k| k.borrow_mut().push(key));
+    0
+}
@@ -0,0 +1,463 @@
+use crate::symbols;
+use std::time::Duration;
+
+/// # 音声記号列を解析した結果の1単位
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mora {
+    /// 読み記号1拍分です
+    Kana {
+        /// 読み記号です｡拗音は「きゃ」のように2文字になります
+        kana: String,
+        /// この拍の直後にアクセント記号(')があるか､つまりこの拍がアクセント核かどうかです
+        accent: bool,
+        /// この拍の直前に無声化記号(_)があるかどうかです
+        devoiced: bool,
+    },
+    /// ポーズ(、や。など)です
+    Pause(char),
+    /// アクセント句の区切り(/)です
+    Boundary,
+    /// <NUMK VAL=123>のようなタグです｡<>の中身が入っています
+    Tag(String),
+}
+
+/// 音声記号列を拍ごとに分解します
+/// DLLは使わず､記号列の文字だけをsymbolsの音声記号の一覧と照らし合わせて解析します
+pub fn parse(koe: &str) -> Result<Vec<Mora>, ParseError> {
+    let mut result = Vec::new();
+    let mut devoiced = false;
+    let mut chars = koe.char_indices();
+    while let Some((pos, ch)) = chars.next() {
+        let reading = symbols::reading_at(&koe[pos..]);
+        if ch != '_' && reading.is_none() && devoiced {
+            return Err(ParseError::MisplacedMark { pos, ch: '_' });
+        }
+        match ch {
+            '_' => {
+                if devoiced {
+                    return Err(ParseError::MisplacedMark { pos, ch });
+                }
+                devoiced = true;
+            }
+            '\'' => match result.last_mut() {
+                Some(Mora::Kana { accent, .. }) if !*accent => *accent = true,
+                _ => return Err(ParseError::MisplacedMark { pos, ch }),
+            },
+            '/' => result.push(Mora::Boundary),
+            '<' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '>')) => break,
+                        Some((_, c)) => tag.push(c),
+                        None => return Err(ParseError::UnclosedTag { pos }),
+                    }
+                }
+                result.push(Mora::Tag(tag));
+            }
+            c if symbols::is_pause(c) => result.push(Mora::Pause(c)),
+            c => {
+                let kana = reading.ok_or(ParseError::UnknownChar { pos, ch: c })?;
+                // 拗音は2文字で1つの読み記号なので､2文字目を読み飛ばします
+                for _ in 1..kana.chars().count() {
+                    chars.next();
+                }
+                result.push(Mora::Kana {
+                    kana: kana.to_string(),
+                    accent: false,
+                    devoiced,
+                });
+                devoiced = false;
+            }
+        }
+    }
+    if devoiced {
+        return Err(ParseError::MisplacedMark {
+            pos: koe.len() - 1,
+            ch: '_',
+        });
+    }
+    Ok(result)
+}
+
+/// アクセント句(/やポーズで区切られた部分)の拍数がmax_moras以下になるように､音声記号列に/を挿入します
+/// AquesTalkでエラーコード201(１つのフレーズ中の読み記号が多すぎる)が出る場合に使えます
+/// 拗音や無声化記号(_)と読み記号の間､アクセント記号(')の前､タグの中では区切りません｡区切った位置のアクセントは変わることがあります
+pub fn split_long_phrases(koe: &str, max_moras: usize) -> String {
+    let max_moras = max_moras.max(1);
+    let mut result = String::with_capacity(koe.len());
+    let mut count = 0;
+    let mut devoiced = false;
+    let mut pos = 0;
+    while let Some(ch) = koe[pos..].chars().next() {
+        let reading = symbols::reading_at(&koe[pos..]);
+        let len = match ch {
+            '<' => koe[pos..].find('>').map_or(koe.len() - pos, |i| i + 1),
+            _ => reading.map_or(ch.len_utf8(), str::len),
+        };
+        if ch == '/' || symbols::is_pause(ch) {
+            count = 0;
+        } else if ch == '_' || reading.is_some() {
+            if !devoiced {
+                if count >= max_moras {
+                    result.push('/');
+                    count = 0;
+                }
+                count += 1;
+            }
+            devoiced = ch == '_';
+        }
+        result.push_str(&koe[pos..pos + len]);
+        pos += len;
+    }
+    result
+}
+
+/// # 読みを仮名とアクセントの位置に分けたもの
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reading {
+    /// 記号を除いた読みの仮名を繋げたものです
+    pub kana: String,
+    /// アクセント核の拍の位置です｡読み全体の先頭の拍を0として数えます(拗音は2文字で1拍です)
+    pub accent_positions: Vec<usize>,
+}
+
+impl Reading {
+    /// parseの結果から､仮名とアクセント核の位置を取り出します｡ポーズや区切り､タグは無視します
+    pub fn from_moras(moras: &[Mora]) -> Self {
+        let mut reading = Reading::default();
+        let mut count = 0;
+        for mora in moras {
+            if let Mora::Kana { kana, accent, .. } = mora {
+                reading.kana.push_str(kana);
+                if *accent {
+                    reading.accent_positions.push(count);
+                }
+                count += 1;
+            }
+        }
+        reading
+    }
+}
+
+/// parseの結果を､他のツールに渡しやすい簡単なマークアップにします｡from_markupで元に戻せます
+/// 形式は次のとおりで､全体を<speak>と</speak>で囲みます
+/// - 読み記号1拍: <m>く</m>｡アクセント核ならaccent="1"､無声化していればdevoiced="1"の属性が付きます
+/// - アクセント句の区切り(/): <boundary/>
+/// - ポーズ: <break char="、"/>
+/// - タグ: <tag value="NUMK VAL=123"/>
+///
+/// 属性の値の&､<､>､"は&amp;などの実体参照にします
+pub fn to_markup(moras: &[Mora]) -> String {
+    let mut result = String::from("<speak>");
+    for mora in moras {
+        match mora {
+            Mora::Kana {
+                kana,
+                accent,
+                devoiced,
+            } => {
+                result.push_str("<m");
+                if *accent {
+                    result.push_str(" accent=\"1\"");
+                }
+                if *devoiced {
+                    result.push_str(" devoiced=\"1\"");
+                }
+                result.push('>');
+                result.push_str(kana);
+                result.push_str("</m>");
+            }
+            Mora::Boundary => result.push_str("<boundary/>"),
+            Mora::Pause(c) => {
+                result.push_str("<break char=\"");
+                result.push_str(&escape(&c.to_string()));
+                result.push_str("\"/>");
+            }
+            Mora::Tag(tag) => {
+                result.push_str("<tag value=\"");
+                result.push_str(&escape(tag));
+                result.push_str("\"/>");
+            }
+        }
+    }
+    result.push_str("</speak>");
+    result
+}
+
+/// to_markupで作ったマークアップを元に戻します
+/// 形式が違う場合はParseError::InvalidMarkupを返します｡posはマークアップの中での位置(バイト単位)です
+pub fn from_markup(markup: &str) -> Result<Vec<Mora>, ParseError> {
+    let body = markup
+        .strip_prefix("<speak>")
+        .and_then(|m| m.strip_suffix("</speak>"))
+        .ok_or(ParseError::InvalidMarkup { pos: 0 })?;
+    let offset = "<speak>".len();
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < body.len() {
+        let err = ParseError::InvalidMarkup { pos: offset + pos };
+        let rest = &body[pos..];
+        let end = rest.find('>').ok_or(err)? + 1;
+        let element = &rest[..end];
+        if element == "<boundary/>" {
+            result.push(Mora::Boundary);
+        } else if let Some(c) = attribute(element, "<break char=\"") {
+            let mut chars = c.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => result.push(Mora::Pause(c)),
+                _ => return Err(err),
+            }
+        } else if let Some(tag) = attribute(element, "<tag value=\"") {
+            result.push(Mora::Tag(tag));
+        } else if element.starts_with("<m") {
+            let close = rest.find("</m>").ok_or(err)?;
+            let kana = rest.get(end..close).ok_or(err)?;
+            result.push(Mora::Kana {
+                kana: kana.to_string(),
+                accent: element.contains(" accent=\"1\""),
+                devoiced: element.contains(" devoiced=\"1\""),
+            });
+            pos += close + "</m>".len();
+            continue;
+        } else {
+            return Err(err);
+        }
+        pos += end;
+    }
+    Ok(result)
+}
+
+/// elementがprefixで始まり"/>で終わる要素なら､属性の値を実体参照を戻して返します
+fn attribute(element: &str, prefix: &str) -> Option<String> {
+    let value = element.strip_prefix(prefix)?.strip_suffix("\"/>")?;
+    Some(
+        value
+            .replace("&quot;", "\"")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// # estimate_timingsが返す､1単位ごとの推定された時間
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoraTiming {
+    /// 対象の拍やポーズです
+    pub mora: Mora,
+    /// 音声の先頭からの開始時刻です
+    pub start: Duration,
+    /// 音声の先頭からの終了時刻です
+    pub end: Duration,
+}
+
+/// parseの結果に､音声全体の長さtotalを拍の長さの目安に比例して割り振ります
+/// 実際に合成された音声を解析しているわけではないので､あくまで推定値です
+/// 目安としては普通の拍を1､無声化した拍を0.5､、などの短いポーズを1､。などの文末を2とし､区切りやタグは0としています
+pub fn estimate_timings(moras: &[Mora], total: Duration) -> Vec<MoraTiming> {
+    let weights: Vec<f64> = moras.iter().map(weight).collect();
+    let sum: f64 = weights.iter().sum();
+    let mut result = Vec::with_capacity(moras.len());
+    let mut elapsed = 0.0;
+    for (mora, w) in moras.iter().zip(weights) {
+        let start = elapsed;
+        elapsed += w;
+        let (start, end) = if sum > 0.0 {
+            (start / sum, elapsed / sum)
+        } else {
+            (0.0, 0.0)
+        };
+        result.push(MoraTiming {
+            mora: mora.clone(),
+            start: total.mul_f64(start),
+            end: total.mul_f64(end),
+        });
+    }
+    result
+}
+
+fn weight(mora: &Mora) -> f64 {
+    match mora {
+        Mora::Kana { devoiced: true, .. } => 0.5,
+        Mora::Kana { .. } => 1.0,
+        Mora::Pause(c) if symbols::SHORT_PAUSES.contains(c) => 1.0,
+        Mora::Pause(_) => 2.0,
+        Mora::Boundary | Mora::Tag(_) => 0.0,
+    }
+}
+
+/// # 音声記号列の解析に失敗したときのエラー
+/// posは音声記号列の中での位置(バイト単位)です
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// 音声記号として使えない文字がある
+    UnknownChar { pos: usize, ch: char },
+    /// アクセント記号(')や無声化記号(_)を付ける読み記号がない
+    MisplacedMark { pos: usize, ch: char },
+    /// タグが>で閉じられていない
+    UnclosedTag { pos: usize },
+    /// from_markupに渡したマークアップの形式が正しくない
+    InvalidMarkup { pos: usize },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::UnknownChar { pos, ch } => {
+                write!(
+                    f,
+                    "音声記号として使えない文字がある, 位置: {}, 文字: {}",
+                    pos, ch
+                )
+            }
+            ParseError::MisplacedMark { pos, ch } => {
+                write!(f, "記号を付ける読み記号がない, 位置: {}, 文字: {}", pos, ch)
+            }
+            ParseError::UnclosedTag { pos } => {
+                write!(f, "タグが閉じられていない, 位置: {}", pos)
+            }
+            ParseError::InvalidMarkup { pos } => {
+                write!(f, "マークアップの形式が正しくない, 位置: {}", pos)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kana(kana: &str, accent: bool, devoiced: bool) -> Mora {
+        Mora::Kana {
+            kana: kana.to_string(),
+            accent,
+            devoiced,
+        }
+    }
+
+    #[test]
+    fn parse_accent_and_pauses() {
+        assert_eq!(
+            parse("きょ'う/_し、").unwrap(),
+            vec![
+                kana("きょ", true, false),
+                kana("う", false, false),
+                Mora::Boundary,
+                kana("し", false, true),
+                Mora::Pause('、'),
+            ]
+        );
+        assert_eq!(
+            parse("<NUMK VAL=1>。").unwrap(),
+            vec![Mora::Tag("NUMK VAL=1".to_string()), Mora::Pause('。')]
+        );
+    }
+
+    #[test]
+    fn parse_error_positions() {
+        assert_eq!(
+            parse("あa"),
+            Err(ParseError::UnknownChar { pos: 3, ch: 'a' })
+        );
+        assert_eq!(
+            parse("'あ"),
+            Err(ParseError::MisplacedMark { pos: 0, ch: '\'' })
+        );
+        assert_eq!(
+            parse("あ''"),
+            Err(ParseError::MisplacedMark { pos: 4, ch: '\'' })
+        );
+        assert_eq!(
+            parse("あ_/"),
+            Err(ParseError::MisplacedMark { pos: 4, ch: '_' })
+        );
+        assert_eq!(
+            parse("あ__い"),
+            Err(ParseError::MisplacedMark { pos: 4, ch: '_' })
+        );
+        // 音声記号の一覧にない文字は､仮名や記号でも受け付けません
+        assert_eq!(
+            parse("あ！"),
+            Err(ParseError::UnknownChar { pos: 3, ch: '！' })
+        );
+        assert_eq!(
+            parse("ゐ"),
+            Err(ParseError::UnknownChar { pos: 0, ch: 'ゐ' })
+        );
+        assert_eq!(parse("あ<NUMK"), Err(ParseError::UnclosedTag { pos: 3 }));
+    }
+
+    #[test]
+    fn split_long_phrases_inserts_boundaries() {
+        assert_eq!(split_long_phrases("あいうえお", 2), "あい/うえ/お");
+        // 拗音と無声化記号の後では区切らず､既存の区切りで数え直します
+        assert_eq!(split_long_phrases("きゃ_しい/うえ", 2), "きゃ_し/い/うえ");
+        assert_eq!(
+            split_long_phrases("あ<NUMK VAL=12>い", 1),
+            "あ<NUMK VAL=12>/い"
+        );
+        assert_eq!(split_long_phrases("あいう", 0), "あ/い/う");
+        assert_eq!(split_long_phrases("あいう", 3), "あいう");
+    }
+
+    #[test]
+    fn reading_from_moras() {
+        let reading = Reading::from_moras(&parse("きょ'う、あ_した'").unwrap());
+        assert_eq!(reading.kana, "きょうあした");
+        assert_eq!(reading.accent_positions, vec![0, 4]);
+    }
+
+    #[test]
+    fn markup_round_trip() {
+        let moras = parse("きょ'う/_し<NUMK VAL=\"1\">、").unwrap();
+        let markup = to_markup(&moras);
+        assert_eq!(
+            markup,
+            "<speak><m accent=\"1\">きょ</m><m>う</m><boundary/><m devoiced=\"1\">し</m>\
+             <tag value=\"NUMK VAL=&quot;1&quot;\"/><break char=\"、\"/></speak>"
+        );
+        assert_eq!(from_markup(&markup).unwrap(), moras);
+        assert_eq!(
+            from_markup("<speak><foo/></speak>"),
+            Err(ParseError::InvalidMarkup { pos: 7 })
+        );
+        assert_eq!(
+            from_markup("<m>あ</m>"),
+            Err(ParseError::InvalidMarkup { pos: 0 })
+        );
+    }
+
+    #[test]
+    fn estimate_timings_covers_whole_clip() {
+        let total = Duration::from_millis(900);
+        let timings = estimate_timings(&parse("あ_し/い。").unwrap(), total);
+        assert_eq!(timings.len(), 5);
+        assert_eq!(timings[0].start, Duration::from_secs(0));
+        assert_eq!(timings.last().unwrap().end, total);
+        for pair in timings.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        // 重みは あ:1 し:0.5 /:0 い:1 。:2 で､合計4.5です
+        let devoiced = timings[1].end - timings[1].start;
+        assert!(devoiced > Duration::from_micros(99_999));
+        assert!(devoiced < Duration::from_micros(100_001));
+        assert_eq!(timings[2].start, timings[2].end);
+        assert!(estimate_timings(&[], total).is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+/// 音声記号の一覧です｡読み記号(1拍分のひらがな)のあとに､アクセントやポーズの記号が続きます
+#[rustfmt::skip]
+static SYMBOLS: &[&str] = &[
+    // 直音
+    "あ", "い", "う", "え", "お", "か", "き", "く", "け", "こ", "さ", "し", "す", "せ", "そ", "た",
+    "ち", "つ", "て", "と", "な", "に", "ぬ", "ね", "の", "は", "ひ", "ふ", "へ", "ほ", "ま", "み",
+    "む", "め", "も", "や", "ゆ", "よ", "ら", "り", "る", "れ", "ろ", "わ", "を", "ん", "が", "ぎ",
+    "ぐ", "げ", "ご", "ざ", "じ", "ず", "ぜ", "ぞ", "だ", "ぢ", "づ", "で", "ど", "ば", "び", "ぶ",
+    "べ", "ぼ", "ぱ", "ぴ", "ぷ", "ぺ", "ぽ", "ゔ",
+    // 促音と長音
+    "っ", "ー",
+    // 拗音
+    "きゃ", "きゅ", "きょ", "しゃ", "しゅ", "しぇ", "しょ", "ちゃ", "ちゅ", "ちぇ", "ちょ", "にゃ",
+    "にゅ", "にょ", "ひゃ", "ひゅ", "ひょ", "みゃ", "みゅ", "みょ", "りゃ", "りゅ", "りょ", "ぎゃ",
+    "ぎゅ", "ぎょ", "じゃ", "じゅ", "じぇ", "じょ", "びゃ", "びゅ", "びょ", "ぴゃ", "ぴゅ", "ぴょ",
+    // 外来音
+    "いぇ", "うぃ", "うぇ", "うぉ", "ゔぁ", "ゔぃ", "ゔぇ", "ゔぉ", "てぃ", "てゅ", "でぃ", "でゅ",
+    "とぅ", "どぅ", "ふぁ", "ふぃ", "ふぇ", "ふぉ", "ふゅ", "つぁ", "つぃ", "つぇ", "つぉ",
+    // アクセント核(直前の拍が高く､次の拍から下がる)
+    "'",
+    // アクセント句の区切り(ポーズなし)
+    "/",
+    // 直後の拍の無声化
+    "_",
+    // 短いポーズ
+    "、", ",", ";",
+    // 文末
+    "。", ".",
+    // 疑問文の文末
+    "？", "?",
+];
+
+/// 文末を表すポーズの記号です｡SYMBOLSの文末と疑問文の文末の記号と同じです
+pub(crate) const SENTENCE_ENDS: &[char] = &['。', '.', '？', '?'];
+
+/// 短いポーズの記号です｡SYMBOLSの短いポーズの記号と同じです
+pub(crate) const SHORT_PAUSES: &[char] = &['、', ',', ';'];
+
+/// chがポーズ(短いポーズか文末)の記号かどうかを返します
+pub(crate) fn is_pause(ch: char) -> bool {
+    SENTENCE_ENDS.contains(&ch) || SHORT_PAUSES.contains(&ch)
+}
+
+/// koeの先頭にある読み記号(1拍分)を､最長一致で返します｡先頭が読み記号でなければNoneを返します
+pub(crate) fn reading_at(koe: &str) -> Option<&'static str> {
+    let mut chars = koe.char_indices();
+    let one = chars.next()?.1.len_utf8();
+    let two = chars.next().map_or(one, |(i, c)| i + c.len_utf8());
+    [two, one]
+        .iter()
+        .filter_map(|len| SYMBOLS.iter().find(|s| **s == &koe[..*len]))
+        .find(|s| is_reading(s))
+        .copied()
+}
+
+fn is_reading(symbol: &str) -> bool {
+    symbol.starts_with(|c: char| ('ぁ'..='ゔ').contains(&c) || c == 'ー')
+}
+
+/// 全ての音声記号の一覧を返します
+/// エディタの入力補完などに使えます
+pub fn all_symbols() -> &'static [&'static str] {
+    SYMBOLS
+}
+
+/// symbolが音声記号の一覧にあるかどうかを返します
+pub fn is_valid_symbol(symbol: &str) -> bool {
+    SYMBOLS.contains(&symbol)
+}
+
+/// 音声記号列koeで使われている音声記号を､最初に現れた順に重複なく返します
+/// 拗音のような2文字の記号は1つの記号として数え､<NUMK VAL=123>のようなタグの中身は無視します
+/// コーパスがどの音素を使っているかの確認などに使えます｡一覧にない文字はunknown_inで調べられます
+pub fn used_in(koe: &str) -> Vec<&'static str> {
+    let mut result = Vec::new();
+    for symbol in split(koe).into_iter().flatten() {
+        if !result.contains(&symbol) {
+            result.push(symbol);
+        }
+    }
+    result
+}
+
+/// 音声記号列koeの中で､音声記号の一覧にない文字を最初に現れた順に重複なく返します
+/// used_inと同じく､タグの中身は無視します
+pub fn unknown_in(koe: &str) -> Vec<char> {
+    let mut result = Vec::new();
+    for ch in split(koe).into_iter().filter_map(Result::err) {
+        if !result.contains(&ch) {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// koeを先頭から最長一致で音声記号に分けます｡一覧にない文字はErrになります
+fn split(koe: &str) -> Vec<Result<&'static str, char>> {
+    let mut result = Vec::new();
+    let mut rest = koe;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '<' {
+            if let Some(end) = rest.find('>') {
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        let two = rest.char_indices().nth(2).map_or(rest, |(i, _)| &rest[..i]);
+        match SYMBOLS
+            .iter()
+            .find(|s| **s == two)
+            .or_else(|| SYMBOLS.iter().find(|s| **s == &rest[..ch.len_utf8()]))
+        {
+            Some(symbol) => {
+                result.push(Ok(*symbol));
+                rest = &rest[symbol.len()..];
+            }
+            None => {
+                result.push(Err(ch));
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_symbols() {
+        for symbol in &["あ", "きゃ", "ゔぁ", "ー", "'", "/", "_", "、", "？"] {
+            assert!(is_valid_symbol(symbol), "{}", symbol);
+        }
+        for garbage in &["", "a", "ゐ", "！", "ゃ", "きゃあ", "<NUMK VAL=1>"] {
+            assert!(!is_valid_symbol(garbage), "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn pauses_match_table() {
+        for c in SENTENCE_ENDS.iter().chain(SHORT_PAUSES) {
+            assert!(is_valid_symbol(&c.to_string()), "{}", c);
+        }
+        let pauses = SYMBOLS
+            .iter()
+            .filter(|s| !is_reading(s) && !["'", "/", "_"].contains(s))
+            .count();
+        assert_eq!(pauses, SENTENCE_ENDS.len() + SHORT_PAUSES.len());
+    }
+
+    #[test]
+    fn reading_at_prefers_longest() {
+        assert_eq!(reading_at("きゃく"), Some("きゃ"));
+        assert_eq!(reading_at("きく"), Some("き"));
+        assert_eq!(reading_at("りぇ"), Some("り"));
+        assert_eq!(reading_at("ゃ"), None);
+        assert_eq!(reading_at("、あ"), None);
+        assert_eq!(reading_at(""), None);
+    }
+
+    #[test]
+    fn used_and_unknown_symbols() {
+        let koe = "きゃく/き'ゃ<NUMK VAL=1>ゐa、";
+        assert_eq!(used_in(koe), vec!["きゃ", "く", "/", "き", "'", "、"]);
+        // 前の文字から離れた小書きの文字は単独では音声記号になりません
+        assert_eq!(unknown_in(koe), vec!['ゃ', 'ゐ', 'a']);
+        assert!(unknown_in("か'んじ。").is_empty());
+        // 閉じていない<は記号として扱います
+        assert_eq!(unknown_in("あ<NUMK"), vec!['<', 'N', 'U', 'M', 'K']);
+    }
+}
@@ -0,0 +1,168 @@
+/// # 単語の読みの上書き指定
+/// readingで作り､AqK2Kinstance::convert_with_readingsに渡して使います
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadingOverride<'a> {
+    /// 入力テキストの中で読みを上書きしたい単語です
+    pub surface: &'a str,
+    /// その単語の読みを表す音声記号列です(例: "か'んじ")
+    pub kana: &'a str,
+}
+
+/// 入力テキスト中のsurfaceを､辞書の読みではなくkana(音声記号列)で読ませるための指定を作ります
+/// 辞書の読みが間違っているけれどユーザ辞書を編集するほどでもない､という場合に使います
+pub fn reading<'a>(surface: &'a str, kana: &'a str) -> ReadingOverride<'a> {
+    ReadingOverride { surface, kana }
+}
+
+/// 音声記号列koeに含まれる<>で囲まれたタグを､AquesTalkに渡す前に確かめます
+/// 確かめるのは次の点で､最初に見つかった問題のあるタグをTagErrorで返します
+/// - タグが>で閉じられているか(AquesTalkのエラーコード107に当たります)
+/// - タグの名前がNUMK､NUM､ALPHAのどれかで､属性がNAME=値の形になっているか(エラーコード106に当たります)
+/// - VALがあり､NUMKとNUMなら半角数字(NUMは.を含んでもかまいません)､ALPHAなら半角英字だけか(エラーコード108に当たります)
+///
+/// COUNTER(助数詞)はNUMKにだけ指定できます｡DLLが実際に受け付ける範囲とは細かい点で違うことがあるので､目安として使ってください
+pub fn validate(koe: &str) -> Result<(), TagError> {
+    let mut rest = koe;
+    let mut offset = 0;
+    while let Some(start) = rest.find('<') {
+        let pos = offset + start;
+        let end = rest[start..].find('>').ok_or(TagError::Unclosed { pos })?;
+        let tag = &rest[start + 1..start + end];
+        validate_tag(pos, tag)?;
+        offset += start + end + 1;
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// posの位置にあるタグの中身tagを確かめます
+fn validate_tag(pos: usize, tag: &str) -> Result<(), TagError> {
+    let mut parts = tag.split(' ').filter(|p| !p.is_empty());
+    let name = parts.next().unwrap_or("");
+    if !["NUMK", "NUM", "ALPHA"].contains(&name) {
+        return Err(TagError::UnknownName {
+            pos,
+            tag: tag.to_string(),
+        });
+    }
+    let mut val = None;
+    for part in parts {
+        match part.split_once('=') {
+            Some(("VAL", v)) if val.is_none() => val = Some(v),
+            Some(("COUNTER", c)) if name == "NUMK" && !c.is_empty() => (),
+            _ => {
+                return Err(TagError::InvalidAttribute {
+                    pos,
+                    tag: tag.to_string(),
+                })
+            }
+        }
+    }
+    let valid = match val {
+        Some(v) if !v.is_empty() => match name {
+            "NUMK" => v.chars().all(|c| c.is_ascii_digit()),
+            "NUM" => v.chars().all(|c| c.is_ascii_digit() || c == '.'),
+            _ => v.chars().all(|c| c.is_ascii_alphabetic()),
+        },
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(TagError::InvalidValue {
+            pos,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+/// # validateで見つかったタグの問題
+/// posは音声記号列の中でのタグの開始位置(バイト単位)で､tagは<>の中身です
+#[derive(Clone, PartialEq, Eq)]
+pub enum TagError {
+    /// タグが>で閉じられていない
+    Unclosed { pos: usize },
+    /// NUMK､NUM､ALPHA以外の名前のタグ
+    UnknownName { pos: usize, tag: String },
+    /// このタグに指定できない属性があるか､属性がNAME=値の形になっていない
+    InvalidAttribute { pos: usize, tag: String },
+    /// VALがないか､値の形が正しくない
+    InvalidValue { pos: usize, tag: String },
+}
+
+impl std::fmt::Display for TagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TagError::Unclosed { pos } => write!(f, "タグが閉じられていない, 位置: {}", pos),
+            TagError::UnknownName { pos, tag } => {
+                write!(f, "知らないタグ, 位置: {}, タグ: <{}>", pos, tag)
+            }
+            TagError::InvalidAttribute { pos, tag } => {
+                write!(f, "タグの属性が正しくない, 位置: {}, タグ: <{}>", pos, tag)
+            }
+            TagError::InvalidValue { pos, tag } => {
+                write!(f, "タグの値が正しくない, 位置: {}, タグ: <{}>", pos, tag)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for TagError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_tags() {
+        for koe in &[
+            "",
+            "あ",
+            "<NUMK VAL=123>",
+            "<NUMK VAL=5 COUNTER=ko>です",
+            "<NUM VAL=3.14>",
+            "<ALPHA VAL=abc>と<NUMK VAL=2>",
+        ] {
+            assert_eq!(validate(koe), Ok(()), "{}", koe);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_tags() {
+        assert_eq!(
+            validate("あ<NUMK VAL=1"),
+            Err(TagError::Unclosed { pos: 3 })
+        );
+        assert_eq!(
+            validate("<FOO VAL=1>"),
+            Err(TagError::UnknownName {
+                pos: 0,
+                tag: "FOO VAL=1".to_string()
+            })
+        );
+        for tag in &["NUM VAL=1 COUNTER=ko", "NUMK VAL=1 VAL=2", "NUMK VAL"] {
+            assert_eq!(
+                validate(&format!("<{}>", tag)),
+                Err(TagError::InvalidAttribute {
+                    pos: 0,
+                    tag: tag.to_string()
+                })
+            );
+        }
+        for tag in &["NUMK VAL=1.5", "NUMK", "NUMK VAL=", "ALPHA VAL=a1"] {
+            assert_eq!(
+                validate(&format!("<NUMK VAL=1>{}<{}>", "あ", tag)),
+                Err(TagError::InvalidValue {
+                    pos: 15,
+                    tag: tag.to_string()
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,255 @@
+/// テキスト中の日付と桁区切りのある数字を､AqKanji2Koeで正しく読まれやすい漢数字の形に書き換えます
+/// 書き換えるのは次の形だけで､それ以外の数字(時刻やバージョン番号､製品名の中の数字など)や文字はそのまま残します
+/// - 2024/01/02や2024-1-2のような年月日 → 二千二十四年一月二日
+/// - 1,234や1,234.5のような3桁区切りの数 → 千二百三十四､千二百三十四点五
+///
+/// 全角数字も半角数字と同じように扱います
+pub fn normalize_numbers(text: &str) -> String {
+    let original: Vec<char> = text.chars().collect();
+    let chars: Vec<char> = original.iter().copied().map(to_ascii_digit).collect();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            result.push(original[i]);
+            i += 1;
+            continue;
+        }
+        match parse_date(&chars, i).or_else(|| parse_grouped(&chars, i)) {
+            Some((reading, end)) => {
+                result.push_str(&reading);
+                i = end;
+            }
+            None => {
+                // 数字の途中から日付などを読み取らないように､数字の並びはまとめてそのまま残します
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    result.push(original[i]);
+                    i += 1;
+                }
+            }
+        }
+    }
+    result
+}
+
+fn to_ascii_digit(c: char) -> char {
+    match c {
+        '０'..='９' => char::from(b'0' + (c as u32 - '０' as u32) as u8),
+        c => c,
+    }
+}
+
+/// chars[start..]が4桁の年と1-2桁の月日を/か-で区切った日付なら､読みと日付の終わりの位置を返します
+fn parse_date(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let digits_at = |pos: usize, min: usize, max: usize| {
+        let len = chars[pos.min(chars.len())..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if (min..=max).contains(&len) {
+            Some((chars[pos..pos + len].iter().collect::<String>(), pos + len))
+        } else {
+            None
+        }
+    };
+    let (year, pos) = digits_at(start, 4, 4)?;
+    let sep = *chars.get(pos).filter(|c| **c == '/' || **c == '-')?;
+    let (month, pos) = digits_at(pos + 1, 1, 2)?;
+    if chars.get(pos) != Some(&sep) {
+        return None;
+    }
+    let (day, pos) = digits_at(pos + 1, 1, 2)?;
+    // 2024-01-02-3のように区切りが続く場合は日付ではないとみなします
+    if chars.get(pos) == Some(&sep) {
+        return None;
+    }
+    let month: u64 = month.parse().ok()?;
+    let day: u64 = day.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some((
+        format!(
+            "{}年{}月{}日",
+            kanji_number(year.parse().ok()?),
+            kanji_number(month),
+            kanji_number(day)
+        ),
+        pos,
+    ))
+}
+
+/// chars[start..]が1-3桁の数字の後に,と3桁の数字が1回以上続く数なら､読みと数の終わりの位置を返します
+/// 後ろに小数点と数字が続く場合は小数として読みます
+fn parse_grouped(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut digits = String::new();
+    let mut pos = start;
+    while pos < chars.len() && chars[pos].is_ascii_digit() {
+        digits.push(chars[pos]);
+        pos += 1;
+    }
+    if digits.len() > 3 || digits.starts_with('0') {
+        return None;
+    }
+    while chars.get(pos) == Some(&',')
+        && chars.len() >= pos + 4
+        && chars[pos + 1..pos + 4].iter().all(char::is_ascii_digit)
+        && !chars.get(pos + 4).is_some_and(char::is_ascii_digit)
+    {
+        digits.extend(&chars[pos + 1..pos + 4]);
+        pos += 4;
+    }
+    if digits.len() <= 3 {
+        return None;
+    }
+    let mut reading = kanji_number(digits.parse().ok()?);
+    if pos + 1 < chars.len() && chars[pos] == '.' && chars[pos + 1].is_ascii_digit() {
+        reading.push('点');
+        pos += 1;
+        while pos < chars.len() && chars[pos].is_ascii_digit() {
+            reading.push(kanji_digit(chars[pos]));
+            pos += 1;
+        }
+    }
+    Some((reading, pos))
+}
+
+fn kanji_digit(c: char) -> char {
+    ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'][c as usize - '0' as usize]
+}
+
+/// nを漢数字にします｡十､百､千の前の一は省きます(千二百三十四のように読みます)
+fn kanji_number(n: u64) -> String {
+    if n == 0 {
+        return "〇".to_string();
+    }
+    let mut result = String::new();
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 10000);
+        rest /= 10000;
+    }
+    for (i, group) in groups.iter().enumerate().rev() {
+        if *group == 0 {
+            continue;
+        }
+        for (place, unit) in [(1000, "千"), (100, "百"), (10, "十")].iter() {
+            let d = group / place % 10;
+            if d > 1 {
+                result.push(kanji_digit(char::from(b'0' + d as u8)));
+            }
+            if d > 0 {
+                result.push_str(unit);
+            }
+        }
+        if group % 10 > 0 {
+            result.push(kanji_digit(char::from(b'0' + (group % 10) as u8)));
+        }
+        result.push_str(["", "万", "億", "兆", "京"][i]);
+    }
+    result
+}
+
+/// 半角カタカナ(と半角の句読点やかぎ括弧)を全角に書き換えます｡ｶﾞやﾊﾟのような濁点･半濁点付きの文字は1文字にまとめます
+/// AqKanji2Koeは半角カタカナの読みを間違えたり､エラーコード107を返したりすることがあるので､貼り付けられたテキストなどを渡す前に使ってください
+pub fn to_fullwidth_kana(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let full = match HALFWIDTH_KANA.iter().position(|h| *h == c) {
+            Some(i) => FULLWIDTH_KANA[i],
+            None => {
+                result.push(c);
+                continue;
+            }
+        };
+        // 全角では濁音は清音の次の文字､半濁音はその次の文字になっています
+        let combined = match chars.peek() {
+            Some('ﾞ') if full == 'ウ' => Some('ヴ'),
+            Some('ﾞ') => std::char::from_u32(full as u32 + 1)
+                .filter(|v| "ガギグゲゴザジズゼゾダヂヅデドバビブベボ".contains(*v)),
+            Some('ﾟ') => {
+                std::char::from_u32(full as u32 + 2).filter(|v| "パピプペポ".contains(*v))
+            }
+            _ => None,
+        };
+        match combined {
+            Some(v) => {
+                result.push(v);
+                chars.next();
+            }
+            None => result.push(full),
+        }
+    }
+    result
+}
+
+#[rustfmt::skip]
+static HALFWIDTH_KANA: [char; 63] = [
+    '｡', '｢', '｣', '､', '･', 'ｦ', 'ｧ', 'ｨ', 'ｩ', 'ｪ', 'ｫ', 'ｬ', 'ｭ', 'ｮ', 'ｯ', 'ｰ',
+    'ｱ', 'ｲ', 'ｳ', 'ｴ', 'ｵ', 'ｶ', 'ｷ', 'ｸ', 'ｹ', 'ｺ', 'ｻ', 'ｼ', 'ｽ', 'ｾ', 'ｿ', 'ﾀ',
+    'ﾁ', 'ﾂ', 'ﾃ', 'ﾄ', 'ﾅ', 'ﾆ', 'ﾇ', 'ﾈ', 'ﾉ', 'ﾊ', 'ﾋ', 'ﾌ', 'ﾍ', 'ﾎ', 'ﾏ', 'ﾐ',
+    'ﾑ', 'ﾒ', 'ﾓ', 'ﾔ', 'ﾕ', 'ﾖ', 'ﾗ', 'ﾘ', 'ﾙ', 'ﾚ', 'ﾛ', 'ﾜ', 'ﾝ', 'ﾞ', 'ﾟ',
+];
+
+#[rustfmt::skip]
+static FULLWIDTH_KANA: [char; 63] = [
+    '。', '「', '」', '、', '・', 'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ', 'ー',
+    'ア', 'イ', 'ウ', 'エ', 'オ', 'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ', 'ソ', 'タ',
+    'チ', 'ツ', 'テ', 'ト', 'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ', 'マ', 'ミ',
+    'ム', 'メ', 'モ', 'ヤ', 'ユ', 'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン', '゛', '゜',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_dates() {
+        assert_eq!(normalize_numbers("2024/01/02"), "二千二十四年一月二日");
+        assert_eq!(
+            normalize_numbers("締切は２０２４-１２-３１です"),
+            "締切は二千二十四年十二月三十一日です"
+        );
+        assert_eq!(normalize_numbers("2024/13/01"), "2024/13/01");
+        assert_eq!(normalize_numbers("2024-01-02-3"), "2024-01-02-3");
+        assert_eq!(normalize_numbers("12024/01/02"), "12024/01/02");
+    }
+
+    #[test]
+    fn normalizes_grouped_numbers() {
+        assert_eq!(normalize_numbers("1,234円"), "千二百三十四円");
+        assert_eq!(
+            normalize_numbers("12,345,678"),
+            "千二百三十四万五千六百七十八"
+        );
+        assert_eq!(normalize_numbers("1,000.25"), "千点二五");
+        assert_eq!(normalize_numbers("1,23"), "1,23");
+        assert_eq!(normalize_numbers("0,123"), "0,123");
+        assert_eq!(normalize_numbers("1,2345"), "1,2345");
+    }
+
+    #[test]
+    fn leaves_other_digits() {
+        let text = "12:05 Windows 10 v1.2.3 ０９０";
+        assert_eq!(normalize_numbers(text), text);
+    }
+
+    #[test]
+    fn kanji_number_places() {
+        assert_eq!(kanji_number(0), "〇");
+        assert_eq!(kanji_number(1010), "千十");
+        assert_eq!(kanji_number(2_0000_0001), "二億一");
+        assert_eq!(kanji_number(1234_5678), "千二百三十四万五千六百七十八");
+    }
+
+    #[test]
+    fn fullwidth_kana_combines_marks() {
+        assert_eq!(to_fullwidth_kana("ｶﾞｷﾞﾊﾟｰﾃｨｰ"), "ガギパーティー");
+        assert_eq!(to_fullwidth_kana("ｳﾞｧｲｵﾘﾝ"), "ヴァイオリン");
+        assert_eq!(to_fullwidth_kana("ｱﾞﾏﾟ"), "ア゛マ゜");
+        assert_eq!(to_fullwidth_kana("｢abc｣､ﾃｽﾄ｡"), "「abc」、テスト。");
+        assert_eq!(to_fullwidth_kana("漢字とかな"), "漢字とかな");
+    }
+}
@@ -0,0 +1,860 @@
+use std::{
+    convert::TryFrom,
+    io::{self, Write},
+    time::Duration,
+};
+
+/// # WAVデータのヘッダーから読み取った情報
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavHeader {
+    /// フォーマットID(リニアPCMなら1)
+    pub format_tag: u16,
+    /// チャンネル数
+    pub channels: u16,
+    /// サンプリングレート(Hz)
+    pub sample_rate: u32,
+    /// 1秒あたりのバイト数
+    pub byte_rate: u32,
+    /// 1サンプル(全チャンネル分)のバイト数
+    pub block_align: u16,
+    /// 1サンプルあたりのビット数
+    pub bits_per_sample: u16,
+    /// dataチャンクの中身が始まる位置(バイト単位)
+    pub data_offset: usize,
+    /// dataチャンクの中身の長さ(バイト単位)
+    pub data_len: usize,
+    /// WAVデータとして有効な部分の長さ(バイト単位)です
+    /// RIFFチャンクのサイズから求め､その後ろにある余分なバイトは含みません｡RIFFチャンクのサイズがおかしい場合はdataチャンクの終わりまでになります
+    pub total_len: usize,
+}
+
+/// # リニアPCMの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PcmSpec {
+    /// サンプリングレート(Hz)
+    pub sample_rate: u32,
+    /// チャンネル数
+    pub channels: u16,
+    /// 1サンプルあたりのビット数
+    pub bits_per_sample: u16,
+}
+
+impl WavHeader {
+    /// ヘッダーのうち､リニアPCMの形式に関わる部分を返します
+    pub fn spec(&self) -> PcmSpec {
+        PcmSpec {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bits_per_sample: self.bits_per_sample,
+        }
+    }
+
+    /// WAVデータのfmtチャンクとdataチャンクを探して､ヘッダーの情報を読み取ります
+    /// 先頭がRIFFとWAVEで始まっていない場合はWavError::NotRiffを返します
+    /// dataチャンクのサイズがデータの長さを超えている場合は､実際にあるところまでをdataチャンクとして扱います
+    pub fn parse(wav: &[u8]) -> Result<Self, WavError> {
+        if wav.len() < 12 {
+            return Err(WavError::TooShort);
+        }
+        if &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+            return Err(WavError::NotRiff);
+        }
+        let mut pos = 12;
+        let mut fmt: Option<(u16, u16, u32, u32, u16, u16)> = None;
+        while pos < wav.len() && wav.len() - pos >= 8 {
+            let id = &wav[pos..pos + 4];
+            let size = read_u32(wav, pos + 4) as usize;
+            let body = pos + 8;
+            if id == b"fmt " {
+                if size < 16 || body + 16 > wav.len() {
+                    return Err(WavError::TooShort);
+                }
+                fmt = Some((
+                    read_u16(wav, body),
+                    read_u16(wav, body + 2),
+                    read_u32(wav, body + 4),
+                    read_u32(wav, body + 8),
+                    read_u16(wav, body + 12),
+                    read_u16(wav, body + 14),
+                ));
+            } else if id == b"data" {
+                let (format_tag, channels, sample_rate, byte_rate, block_align, bits_per_sample) =
+                    fmt.ok_or(WavError::NoFmtChunk)?;
+                let data_len = size.min(wav.len() - body);
+                let data_end = body + data_len;
+                let riff_end = (read_u32(wav, 4) as usize).saturating_add(8);
+                return Ok(WavHeader {
+                    format_tag,
+                    channels,
+                    sample_rate,
+                    byte_rate,
+                    block_align,
+                    bits_per_sample,
+                    data_offset: body,
+                    data_len,
+                    total_len: if riff_end >= data_end && riff_end <= wav.len() {
+                        riff_end
+                    } else {
+                        data_end
+                    },
+                });
+            }
+            // チャンクは2バイト境界に揃えられています
+            pos = body.saturating_add(size).saturating_add(size & 1);
+        }
+        Err(WavError::NoDataChunk)
+    }
+
+    /// dataチャンクの長さから再生時間を計算します
+    pub fn duration(&self) -> Duration {
+        if self.byte_rate == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(self.data_len as f64 / f64::from(self.byte_rate))
+    }
+}
+
+/// # Rust側で持っているWAVデータ
+/// DLLが返したAqWAVと違い､DLLが開放されても使い続けられます
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedWav {
+    wav: Vec<u8>,
+}
+
+impl OwnedWav {
+    /// WAVデータを包みます｡ヘッダーを読み取れない場合はエラーを返します
+    /// RIFFチャンクの後ろにある余分なバイトは取り除くので､append_pcmやwith_metadataで書き足したチャンクがその後ろに来ることはありません
+    pub fn new(mut wav: Vec<u8>) -> Result<Self, WavError> {
+        let header = WavHeader::parse(&wav)?;
+        wav.truncate(header.total_len);
+        Ok(OwnedWav { wav })
+    }
+
+    /// ヘッダーの情報を返します
+    pub fn header(&self) -> WavHeader {
+        // newで検証済みなので失敗しません
+        WavHeader::parse(&self.wav).unwrap()
+    }
+
+    /// WAVデータ全体をバイト列として返します
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.wav
+    }
+
+    /// 包んでいるバイト列を取り出します
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.wav
+    }
+
+    /// 16bitのサンプルをdataチャンクの末尾に加え､dataチャンクとRIFFチャンクのサイズを書き換えます
+    /// 効果音やジングルを繋げるのに使えます｡16bitリニアPCMでない場合はエラーを返します(複数チャンネルの場合はインターリーブしたサンプルを渡してください)
+    pub fn append_pcm(&mut self, samples: &[i16]) -> Result<(), WavError> {
+        let header = self.header();
+        if header.format_tag != 1 || header.bits_per_sample != 16 {
+            return Err(WavError::UnsupportedFormat);
+        }
+        let data_end = header.data_offset + header.data_len;
+        let data_len =
+            u32::try_from(header.data_len + samples.len() * 2).map_err(|_| WavError::TooLong)?;
+        let riff_len =
+            u32::try_from(self.wav.len() - 8 + samples.len() * 2).map_err(|_| WavError::TooLong)?;
+        let bytes = samples.iter().flat_map(|s| s.to_le_bytes());
+        self.wav.splice(data_end..data_end, bytes);
+        self.wav[header.data_offset - 4..header.data_offset]
+            .copy_from_slice(&data_len.to_le_bytes());
+        self.wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        Ok(())
+    }
+
+    /// 元のテキストと発話速度をLISTチャンク(INFO)として末尾に加えたWAVデータを返します
+    /// テキストはINAMに､発話速度はICMTに"speed=100"のような形で､どちらもUTF-8で書き込みます｡RIFFチャンクのサイズも書き換えるので､普通のプレイヤーでそのまま再生できます
+    pub fn with_metadata(&self, text: &str, speed: i32) -> Result<Vec<u8>, WavError> {
+        let mut list = b"INFO".to_vec();
+        for (id, value) in &[
+            (b"INAM", text.to_string()),
+            (b"ICMT", format!("speed={}", speed)),
+            (b"ISFT", "aquestalk_rs".to_string()),
+        ] {
+            // 文字列はNUL終端で､チャンクは2バイト境界に揃えます
+            let len = value.len() + 1;
+            list.extend_from_slice(*id);
+            list.extend_from_slice(
+                &u32::try_from(len)
+                    .map_err(|_| WavError::TooLong)?
+                    .to_le_bytes(),
+            );
+            list.extend_from_slice(value.as_bytes());
+            list.push(0);
+            if len % 2 == 1 {
+                list.push(0);
+            }
+        }
+        let mut wav = self.wav.clone();
+        if wav.len() % 2 == 1 {
+            wav.push(0);
+        }
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(
+            &u32::try_from(list.len())
+                .map_err(|_| WavError::TooLong)?
+                .to_le_bytes(),
+        );
+        wav.extend_from_slice(&list);
+        let riff_len = u32::try_from(wav.len() - 8).map_err(|_| WavError::TooLong)?;
+        wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        Ok(wav)
+    }
+}
+
+impl std::ops::Deref for OwnedWav {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.wav
+    }
+}
+
+impl AsRef<[u8]> for OwnedWav {
+    fn as_ref(&self) -> &[u8] {
+        &self.wav
+    }
+}
+
+/// WAVデータのLISTチャンク(INFO)の中身を､IDと文字列の組で返します｡with_metadataで書き込んだ情報を読み取るのに使えます
+/// 文字列の末尾のNULは取り除き､UTF-8として不正な部分は置き換えます
+pub fn info_entries(wav: &[u8]) -> Vec<([u8; 4], String)> {
+    let mut result = Vec::new();
+    let mut pos = 12;
+    while pos < wav.len() && wav.len() - pos >= 8 {
+        let size = read_u32(wav, pos + 4) as usize;
+        let body = pos + 8;
+        let end = body.saturating_add(size).min(wav.len());
+        if &wav[pos..pos + 4] == b"LIST" && end - body >= 4 && &wav[body..body + 4] == b"INFO" {
+            let mut sub = body + 4;
+            while sub < end && end - sub >= 8 {
+                let sub_size = read_u32(wav, sub + 4) as usize;
+                let value = &wav[sub + 8..sub.saturating_add(8).saturating_add(sub_size).min(end)];
+                let value = value.split(|&b| b == 0).next().unwrap_or(&[]);
+                result.push((
+                    [wav[sub], wav[sub + 1], wav[sub + 2], wav[sub + 3]],
+                    String::from_utf8_lossy(value).into_owned(),
+                ));
+                sub = sub
+                    .saturating_add(8)
+                    .saturating_add(sub_size)
+                    .saturating_add(sub_size & 1);
+            }
+        }
+        pos = body.saturating_add(size).saturating_add(size & 1);
+    }
+    result
+}
+
+/// 2つのWAVデータが同じ音声かどうかを､fmtチャンクの内容とdataチャンクの中身だけで比べます
+/// LISTチャンク(INFO)などの他のチャンクや､チャンクの並びと末尾の余分なバイトの違いは無視するので､with_metadataで情報を加えたWAVデータも元のWAVデータと等しくなります
+pub fn wav_data_eq(a: &[u8], b: &[u8]) -> Result<bool, WavError> {
+    let (header_a, header_b) = (WavHeader::parse(a)?, WavHeader::parse(b)?);
+    let fmt = |h: &WavHeader| {
+        (
+            h.format_tag,
+            h.channels,
+            h.sample_rate,
+            h.byte_rate,
+            h.block_align,
+            h.bits_per_sample,
+        )
+    };
+    fn data<'a>(wav: &'a [u8], h: &WavHeader) -> &'a [u8] {
+        &wav[h.data_offset..h.data_offset + h.data_len]
+    }
+    Ok(fmt(&header_a) == fmt(&header_b) && data(a, &header_a) == data(b, &header_b))
+}
+
+/// headerのfmtチャンクの内容と､data_lenバイトのdataチャンクを持つWAVデータの先頭44バイトを作ります
+pub(crate) fn header_bytes(header: &WavHeader, data_len: u32) -> [u8; 44] {
+    let mut bytes = [0; 44];
+    bytes[0..4].copy_from_slice(b"RIFF");
+    bytes[4..8].copy_from_slice(&data_len.saturating_add(36).to_le_bytes());
+    bytes[8..12].copy_from_slice(b"WAVE");
+    bytes[12..16].copy_from_slice(b"fmt ");
+    bytes[16..20].copy_from_slice(&16u32.to_le_bytes());
+    bytes[20..22].copy_from_slice(&header.format_tag.to_le_bytes());
+    bytes[22..24].copy_from_slice(&header.channels.to_le_bytes());
+    bytes[24..28].copy_from_slice(&header.sample_rate.to_le_bytes());
+    bytes[28..32].copy_from_slice(&header.byte_rate.to_le_bytes());
+    bytes[32..34].copy_from_slice(&header.block_align.to_le_bytes());
+    bytes[34..36].copy_from_slice(&header.bits_per_sample.to_le_bytes());
+    bytes[36..40].copy_from_slice(b"data");
+    bytes[40..44].copy_from_slice(&data_len.to_le_bytes());
+    bytes
+}
+
+/// サンプリングレートがrateのとき､長さdurationに当たるサンプル数(1チャンネル分)を返します｡端数は四捨五入します
+pub fn samples_for(duration: Duration, rate: u32) -> usize {
+    (duration.as_secs_f64() * f64::from(rate)).round() as usize
+}
+
+/// サンプリングレートがrateのとき､samples個のサンプル(1チャンネル分)の長さを返します｡rateが0の場合は0秒を返します
+pub fn duration_for(samples: usize, rate: u32) -> Duration {
+    if rate == 0 {
+        return Duration::from_secs(0);
+    }
+    Duration::from_secs_f64(samples as f64 / f64::from(rate))
+}
+
+/// specの形式で､長さがdurationの無音のWAVデータを作ります
+/// 音声の間に挟むポーズなどに使えます｡8bitの場合は無音を表す0x80で埋めます
+/// 1サンプルあたりのビット数が8の倍数でない場合や､ブロックサイズ(チャンネル数×バイト数)が65535を超える場合はWavError::InvalidParameterを返します
+pub fn silence_wav(spec: PcmSpec, duration: Duration) -> Result<Vec<u8>, WavError> {
+    if spec.bits_per_sample == 0 || !spec.bits_per_sample.is_multiple_of(8) {
+        return Err(WavError::InvalidParameter);
+    }
+    let block_align = u16::try_from(u32::from(spec.channels) * u32::from(spec.bits_per_sample) / 8)
+        .map_err(|_| WavError::InvalidParameter)?;
+    let samples = samples_for(duration, spec.sample_rate) as u64;
+    let data_len = samples
+        .checked_mul(u64::from(block_align))
+        .and_then(|len| u32::try_from(len).ok())
+        .filter(|len| *len <= u32::MAX - 36)
+        .ok_or(WavError::TooLong)?;
+    let header = WavHeader {
+        format_tag: 1,
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+        byte_rate: spec.sample_rate.saturating_mul(u32::from(block_align)),
+        block_align,
+        bits_per_sample: spec.bits_per_sample,
+        data_offset: 44,
+        data_len: data_len as usize,
+        total_len: 44 + data_len as usize,
+    };
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(&header_bytes(&header, data_len));
+    let fill = if spec.bits_per_sample == 8 { 0x80 } else { 0 };
+    wav.resize(44 + data_len as usize, fill);
+    Ok(wav)
+}
+
+/// サンプリングレートがrateで長さがdurationの､モノラル16bitの無音のサンプルを作ります
+pub fn silence_pcm(rate: u32, duration: Duration) -> Vec<i16> {
+    vec![0; samples_for(duration, rate)]
+}
+
+/// WAVデータのfmtチャンクのサンプリングレートをrateに書き換え､1秒あたりのバイト数もそれに合わせて書き換えます
+/// PCMデータはそのままなので､再生速度と音の高さが変わります(例えば8000Hzの音声を16000Hzにすると2倍速で高い声になります)
+pub fn set_sample_rate_tag(wav: &mut [u8], rate: u32) -> Result<(), WavError> {
+    let header = WavHeader::parse(wav)?;
+    let mut pos = 12;
+    while pos < header.data_offset {
+        let size = read_u32(wav, pos + 4) as usize;
+        let body = pos + 8;
+        if &wav[pos..pos + 4] == b"fmt " {
+            let byte_rate = rate.saturating_mul(u32::from(header.block_align));
+            wav[body + 4..body + 8].copy_from_slice(&rate.to_le_bytes());
+            wav[body + 8..body + 12].copy_from_slice(&byte_rate.to_le_bytes());
+        }
+        pos = body.saturating_add(size).saturating_add(size & 1);
+    }
+    Ok(())
+}
+
+/// 16bitリニアPCMのWAVデータからサンプルを取り出します(複数チャンネルの場合はインターリーブされたまま返します)
+pub fn pcm_i16(wav: &[u8]) -> Result<Vec<i16>, WavError> {
+    let header = WavHeader::parse(wav)?;
+    if header.format_tag != 1 || header.bits_per_sample != 16 {
+        return Err(WavError::UnsupportedFormat);
+    }
+    let data = &wav[header.data_offset..header.data_offset + header.data_len];
+    Ok(data
+        .chunks_exact(2)
+        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+        .collect())
+}
+
+/// 16bitリニアPCMのWAVデータのサンプルを上位8bitだけ残して､8bitの符号なしのサンプル(無音が128)の列に変換します
+pub fn pcm_u8(wav: &[u8]) -> Result<Vec<u8>, WavError> {
+    Ok(pcm_i16(wav)?
+        .into_iter()
+        .map(|s| ((i32::from(s) + 32768) >> 8) as u8)
+        .collect())
+}
+
+/// 16bitリニアPCMのWAVデータのサンプルをsizeサンプルずつ､hopサンプルずつずらしながら切り出し､ハン窓をかけたフレームを返します
+/// サンプルは32768.0で割って-1.0から1.0の範囲にしてあり､そのままFFTに渡してスペクトログラムを作れます
+/// 末尾のsizeに満たない部分は捨てるので､フレームの数はサンプル数がsize以上なら(サンプル数 - size) / hop + 1です｡sizeが0の場合はフレームを返さず､hopが0の場合は1として扱います
+pub fn windows(
+    wav: &[u8],
+    size: usize,
+    hop: usize,
+) -> Result<impl Iterator<Item = Vec<f32>>, WavError> {
+    let samples: Vec<f32> = pcm_i16(wav)?
+        .into_iter()
+        .map(|s| f32::from(s) / 32768.0)
+        .collect();
+    let hop = hop.max(1);
+    let window: Vec<f32> = (0..size)
+        .map(|n| {
+            if size == 1 {
+                1.0
+            } else {
+                let phase = 2.0 * std::f32::consts::PI * n as f32 / (size - 1) as f32;
+                0.5 - 0.5 * phase.cos()
+            }
+        })
+        .collect();
+    let count = if size == 0 || samples.len() < size {
+        0
+    } else {
+        (samples.len() - size) / hop + 1
+    };
+    Ok((0..count).map(move |i| {
+        samples[i * hop..i * hop + size]
+            .iter()
+            .zip(&window)
+            .map(|(s, w)| s * w)
+            .collect()
+    }))
+}
+
+/// 16bitリニアPCMのWAVデータに1次のローパスフィルターをかけ､その場で書き換えます
+/// AquesTalkの8kHzの音声をアップサンプリングしたときに出る､耳障りな高音を和らげるのに使えます
+/// cutoff_hzは遮断周波数で､この周波数より高い音ほど小さくなります
+/// cutoff_hzが0以下かNaNや無限大の場合､またはヘッダーのサンプリングレートが0の場合はWavError::InvalidParameterを返します
+pub fn low_pass(wav: &mut [u8], cutoff_hz: f32) -> Result<(), WavError> {
+    let header = WavHeader::parse(wav)?;
+    if header.format_tag != 1 || header.bits_per_sample != 16 || header.channels == 0 {
+        return Err(WavError::UnsupportedFormat);
+    }
+    if !cutoff_hz.is_finite() || cutoff_hz <= 0.0 || header.sample_rate == 0 {
+        return Err(WavError::InvalidParameter);
+    }
+    let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff_hz);
+    let dt = 1.0 / header.sample_rate as f32;
+    let alpha = dt / (rc + dt);
+    let channels = usize::from(header.channels);
+    let mut state = vec![0.0f32; channels];
+    let data = &mut wav[header.data_offset..header.data_offset + header.data_len];
+    for (i, sample) in data.chunks_exact_mut(2).enumerate() {
+        let prev = &mut state[i % channels];
+        let x = f32::from(i16::from_le_bytes([sample[0], sample[1]]));
+        *prev += alpha * (x - *prev);
+        // asでの変換はi16の範囲に収まるように丸められます
+        let y = prev.round() as i16;
+        sample.copy_from_slice(&y.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// 8bitリニアPCM(符号なし､無音が128)のサンプルをWAVデータとしてwriterに書き出します
+pub fn write_wav_u8<W: Write>(
+    mut writer: W,
+    samples: &[u8],
+    sample_rate: u32,
+    channels: u16,
+) -> io::Result<()> {
+    let data_len = u32::try_from(samples.len())
+        .ok()
+        .filter(|len| *len <= u32::MAX - 36)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, WavError::TooLong))?;
+    let header = WavHeader {
+        format_tag: 1,
+        channels,
+        sample_rate,
+        byte_rate: sample_rate.saturating_mul(u32::from(channels)),
+        block_align: channels,
+        bits_per_sample: 8,
+        data_offset: 44,
+        data_len: samples.len(),
+        total_len: 44 + samples.len(),
+    };
+    writer.write_all(&header_bytes(&header, data_len))?;
+    writer.write_all(samples)
+}
+
+/// インターリーブされたサンプルをチャンネルごとに分けます｡最後の不完全なサンプルは捨てます
+/// channelsが0の場合は空のVecを返します
+pub fn deinterleave(samples: &[i16], channels: u16) -> Vec<Vec<i16>> {
+    let channels = usize::from(channels);
+    // vec![v; n]はcloneで複製するので容量が引き継がれず､チャンネルごとに確保し直します
+    let n = samples.len() / channels.max(1);
+    let mut result: Vec<Vec<i16>> = (0..channels).map(|_| Vec::with_capacity(n)).collect();
+    if channels == 0 {
+        return result;
+    }
+    for frame in samples.chunks_exact(channels) {
+        for (channel, sample) in result.iter_mut().zip(frame) {
+            channel.push(*sample);
+        }
+    }
+    result
+}
+
+/// チャンネルごとのサンプルをインターリーブして1つに繋げます｡deinterleaveの逆です
+/// チャンネルごとの長さが違う場合は､一番短いチャンネルに合わせます
+pub fn interleave(channels: &[Vec<i16>]) -> Vec<i16> {
+    let len = channels.iter().map(Vec::len).min().unwrap_or(0);
+    let mut result = Vec::with_capacity(len * channels.len());
+    for i in 0..len {
+        result.extend(channels.iter().map(|channel| channel[i]));
+    }
+    result
+}
+
+fn read_u16(wav: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([wav[pos], wav[pos + 1]])
+}
+
+fn read_u32(wav: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([wav[pos], wav[pos + 1], wav[pos + 2], wav[pos + 3]])
+}
+
+/// # WAVデータの解析に失敗したときのエラー
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WavError {
+    /// データが短すぎてヘッダーを読み取れない
+    TooShort,
+    /// 先頭がRIFFやWAVEで始まっておらず､RIFF形式のWAVデータではない
+    NotRiff,
+    /// dataチャンクより前にfmtチャンクがない
+    NoFmtChunk,
+    /// dataチャンクが見つからない
+    NoDataChunk,
+    /// 16bitリニアPCM以外の形式には対応していない
+    UnsupportedFormat,
+    /// PCMデータが2バイト境界に揃っていないか､ビッグエンディアンの環境のため､コピーせずに読めない
+    Unaligned,
+    /// WAVデータが長すぎる(4GBを超える)
+    TooLong,
+    /// 遮断周波数やサンプリングレート､PCMの形式など､処理に使う値が正しくない
+    InvalidParameter,
+}
+
+impl WavError {
+    fn msg(&self) -> &str {
+        match self {
+            WavError::TooShort => "WAVデータが短すぎる",
+            WavError::NotRiff => "RIFF形式のWAVデータではない",
+            WavError::NoFmtChunk => "fmtチャンクが見つからない",
+            WavError::NoDataChunk => "dataチャンクが見つからない",
+            WavError::UnsupportedFormat => "16bitリニアPCM以外の形式には対応していない",
+            WavError::Unaligned => "PCMデータをコピーせずに読めない",
+            WavError::TooLong => "WAVデータが長すぎる",
+            WavError::InvalidParameter => {
+                "遮断周波数やサンプリングレート､PCMの形式などの値が正しくない"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for WavError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::fmt::Debug for WavError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::error::Error for WavError {
+    fn description(&self) -> &str {
+        self.msg()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> PcmSpec {
+        PcmSpec {
+            sample_rate: 8000,
+            channels: 1,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// 8000Hzのモノラル16bitのWAVデータを作ります
+    fn wav_i16(samples: &[i16]) -> Vec<u8> {
+        let data_len = samples.len() * 2;
+        let header = WavHeader {
+            format_tag: 1,
+            channels: 1,
+            sample_rate: 8000,
+            byte_rate: 16000,
+            block_align: 2,
+            bits_per_sample: 16,
+            data_offset: 44,
+            data_len,
+            total_len: 44 + data_len,
+        };
+        let mut wav = header_bytes(&header, data_len as u32).to_vec();
+        wav.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+        wav
+    }
+
+    #[test]
+    fn parse_reads_header() {
+        let header = WavHeader::parse(&wav_i16(&[1, 2, 3])).unwrap();
+        assert_eq!(header.spec(), spec());
+        assert_eq!(header.format_tag, 1);
+        assert_eq!(header.byte_rate, 16000);
+        assert_eq!(header.block_align, 2);
+        assert_eq!(header.data_offset, 44);
+        assert_eq!(header.data_len, 6);
+        assert_eq!(header.total_len, 50);
+    }
+
+    #[test]
+    fn parse_rejects_broken_data() {
+        assert_eq!(WavHeader::parse(b"RIFF"), Err(WavError::TooShort));
+        let mut wav = wav_i16(&[1]);
+        wav[8..12].copy_from_slice(b"AVI ");
+        assert_eq!(WavHeader::parse(&wav), Err(WavError::NotRiff));
+        let mut wav = b"RIFF\x0c\0\0\0WAVEdata\0\0\0\0".to_vec();
+        assert_eq!(WavHeader::parse(&wav), Err(WavError::NoFmtChunk));
+        wav.truncate(12);
+        assert_eq!(WavHeader::parse(&wav), Err(WavError::NoDataChunk));
+    }
+
+    #[test]
+    fn parse_excludes_trailing_bytes() {
+        let mut wav = wav_i16(&[1, 2, 3]);
+        let len = wav.len();
+        wav.extend_from_slice(&[0xaa; 5]);
+        let header = WavHeader::parse(&wav).unwrap();
+        assert_eq!(header.data_len, 6);
+        assert_eq!(header.total_len, len);
+    }
+
+    #[test]
+    fn parse_clamps_data_len_to_buffer() {
+        let mut wav = wav_i16(&[1, 2, 3]);
+        wav[40..44].copy_from_slice(&100u32.to_le_bytes());
+        let header = WavHeader::parse(&wav).unwrap();
+        assert_eq!(header.data_len, 6);
+        assert_eq!(header.total_len, wav.len());
+    }
+
+    #[test]
+    fn silence_wav_has_requested_length() {
+        let wav = silence_wav(spec(), Duration::from_millis(100)).unwrap();
+        let header = WavHeader::parse(&wav).unwrap();
+        assert_eq!(wav.len(), 44 + 1600);
+        assert_eq!(header.duration(), Duration::from_millis(100));
+        assert!(wav[44..].iter().all(|b| *b == 0));
+        let spec8 = PcmSpec {
+            bits_per_sample: 8,
+            ..spec()
+        };
+        let wav = silence_wav(spec8, Duration::from_millis(10)).unwrap();
+        assert_eq!(wav.len(), 44 + 80);
+        assert!(wav[44..].iter().all(|b| *b == 0x80));
+    }
+
+    #[test]
+    fn silence_wav_rejects_bits_not_multiple_of_8() {
+        for bits in [0, 12] {
+            let spec = PcmSpec {
+                bits_per_sample: bits,
+                ..spec()
+            };
+            assert!(matches!(
+                silence_wav(spec, Duration::from_millis(10)),
+                Err(WavError::InvalidParameter)
+            ));
+        }
+    }
+
+    #[test]
+    fn silence_wav_rejects_large_block_align() {
+        // 4096チャンネル×16バイトで､ブロックサイズがちょうど65536になります
+        let spec = PcmSpec {
+            channels: 4096,
+            bits_per_sample: 128,
+            ..spec()
+        };
+        assert!(matches!(
+            silence_wav(spec, Duration::from_millis(10)),
+            Err(WavError::InvalidParameter)
+        ));
+        let spec = PcmSpec {
+            channels: 4095,
+            ..spec
+        };
+        assert!(silence_wav(spec, Duration::from_micros(125)).is_ok());
+    }
+
+    #[test]
+    fn silence_wav_rejects_overflowing_length() {
+        let spec = PcmSpec {
+            sample_rate: u32::MAX,
+            ..spec()
+        };
+        // サンプル数×ブロックサイズがu64でも桁あふれします
+        assert!(matches!(
+            silence_wav(spec, Duration::MAX),
+            Err(WavError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn set_sample_rate_tag_keeps_pcm() {
+        let mut wav = wav_i16(&[1, 2, 3]);
+        set_sample_rate_tag(&mut wav, 16000).unwrap();
+        let header = WavHeader::parse(&wav).unwrap();
+        assert_eq!(header.sample_rate, 16000);
+        assert_eq!(header.byte_rate, 32000);
+        assert_eq!(pcm_i16(&wav).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn write_wav_u8_header() {
+        let samples = [128, 0, 255, 128, 64, 192];
+        let mut out = Vec::new();
+        write_wav_u8(&mut out, &samples, 11025, 2).unwrap();
+        assert_eq!(out.len(), 44 + samples.len());
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(&out[4..8], &(36 + samples.len() as u32).to_le_bytes());
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(&out[44..], &samples);
+        let header = WavHeader::parse(&out).unwrap();
+        assert_eq!(header.format_tag, 1);
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.sample_rate, 11025);
+        assert_eq!(header.byte_rate, 22050);
+        assert_eq!(header.block_align, 2);
+        assert_eq!(header.bits_per_sample, 8);
+        assert_eq!(header.data_len, samples.len());
+        assert_eq!(header.total_len, out.len());
+    }
+
+    #[test]
+    fn interleave_round_trip() {
+        let channels = deinterleave(&[1, 2, 3, 4, 5], 2);
+        assert_eq!(channels, vec![vec![1, 3], vec![2, 4]]);
+        assert!(channels.iter().all(|c| c.capacity() >= 2));
+        assert_eq!(interleave(&channels), vec![1, 2, 3, 4]);
+        assert!(deinterleave(&[1, 2], 0).is_empty());
+        assert_eq!(interleave(&[vec![1, 2, 3], vec![4]]), vec![1, 4]);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let wav = OwnedWav::new(wav_i16(&[1, 2, 3])).unwrap();
+        let tagged = wav.with_metadata("こんにちは", 120).unwrap();
+        assert_eq!(read_u32(&tagged, 4) as usize, tagged.len() - 8);
+        assert_eq!(
+            info_entries(&tagged),
+            vec![
+                (*b"INAM", "こんにちは".to_string()),
+                (*b"ICMT", "speed=120".to_string()),
+                (*b"ISFT", "aquestalk_rs".to_string()),
+            ]
+        );
+        assert_eq!(pcm_i16(&tagged).unwrap(), vec![1, 2, 3]);
+        assert!(info_entries(&wav).is_empty());
+    }
+
+    #[test]
+    fn owned_wav_drops_trailing_bytes() {
+        let mut bytes = wav_i16(&[1, 2, 3]);
+        let len = bytes.len();
+        bytes.extend_from_slice(&[0xaa; 5]);
+        let wav = OwnedWav::new(bytes).unwrap();
+        assert_eq!(wav.len(), len);
+        let tagged = wav.with_metadata("あ", 100).unwrap();
+        assert_eq!(read_u32(&tagged, 4) as usize, tagged.len() - 8);
+        assert_eq!(info_entries(&tagged).len(), 3);
+        assert_eq!(pcm_i16(&tagged).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn append_pcm_updates_sizes() {
+        let mut wav = OwnedWav::new(wav_i16(&[1, 2, 3])).unwrap();
+        wav.append_pcm(&[7, 8]).unwrap();
+        assert_eq!(pcm_i16(&wav).unwrap(), vec![1, 2, 3, 7, 8]);
+        assert_eq!(wav.header().data_len, 10);
+        assert_eq!(read_u32(&wav, 4) as usize, wav.len() - 8);
+        let spec8 = PcmSpec {
+            bits_per_sample: 8,
+            ..spec()
+        };
+        let mut wav8 =
+            OwnedWav::new(silence_wav(spec8, Duration::from_millis(1)).unwrap()).unwrap();
+        assert_eq!(wav8.append_pcm(&[1]), Err(WavError::UnsupportedFormat));
+    }
+
+    #[test]
+    fn wav_data_eq_ignores_metadata() {
+        let wav = OwnedWav::new(wav_i16(&[1, 2, 3])).unwrap();
+        let tagged = wav.with_metadata("テスト", 100).unwrap();
+        assert!(wav_data_eq(&wav, &tagged).unwrap());
+        assert!(!wav_data_eq(&wav, &wav_i16(&[1, 2, 4])).unwrap());
+        let mut fast = wav.to_vec();
+        set_sample_rate_tag(&mut fast, 16000).unwrap();
+        assert!(!wav_data_eq(&wav, &fast).unwrap());
+        assert_eq!(wav_data_eq(&wav, b"RIFF"), Err(WavError::TooShort));
+    }
+
+    #[test]
+    fn samples_and_duration_convert() {
+        assert_eq!(samples_for(Duration::from_secs(1), 8000), 8000);
+        assert_eq!(samples_for(Duration::from_micros(1500), 8000), 12);
+        assert_eq!(duration_for(8000, 8000), Duration::from_secs(1));
+        assert_eq!(duration_for(4, 8000), Duration::from_micros(500));
+        assert_eq!(duration_for(100, 0), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn windows_count_and_shape() {
+        let wav = wav_i16(&[16384; 10]);
+        let frames: Vec<Vec<f32>> = windows(&wav, 4, 2).unwrap().collect();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|f| f.len() == 4));
+        // ハン窓の両端は0です
+        assert_eq!(frames[0][0], 0.0);
+        assert!(frames[0][1] > 0.0 && frames[0][1] < 0.5);
+        assert_eq!(windows(&wav, 4, 0).unwrap().count(), 7);
+        assert_eq!(windows(&wav, 0, 1).unwrap().count(), 0);
+        assert_eq!(windows(&wav, 11, 1).unwrap().count(), 0);
+        assert_eq!(windows(&wav, 1, 1).unwrap().next(), Some(vec![0.5]));
+    }
+
+    #[test]
+    fn pcm_u8_keeps_upper_bits() {
+        let wav = wav_i16(&[0, -32768, 32767, 256, -256]);
+        assert_eq!(pcm_u8(&wav).unwrap(), vec![128, 0, 255, 129, 127]);
+    }
+
+    #[test]
+    fn low_pass_attenuates_high_frequency() {
+        // 4000Hz(8000Hzのナイキスト周波数)の矩形波は､500Hzのローパスフィルターでほとんど消えます
+        let samples: Vec<i16> = (0..800)
+            .map(|i| if i % 2 == 0 { 10000 } else { -10000 })
+            .collect();
+        let mut wav = wav_i16(&samples);
+        low_pass(&mut wav, 500.0).unwrap();
+        let filtered = pcm_i16(&wav).unwrap();
+        assert!(filtered[100..].iter().all(|s| s.abs() < 3000));
+        // 直流成分はそのまま通ります
+        let mut wav = wav_i16(&[1000; 800]);
+        low_pass(&mut wav, 500.0).unwrap();
+        assert_eq!(*pcm_i16(&wav).unwrap().last().unwrap(), 1000);
+    }
+
+    #[test]
+    fn low_pass_rejects_invalid_parameters() {
+        let mut wav = wav_i16(&[1, 2, 3]);
+        for cutoff in &[0.0, -100.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(low_pass(&mut wav, *cutoff), Err(WavError::InvalidParameter));
+        }
+        assert_eq!(pcm_i16(&wav).unwrap(), vec![1, 2, 3]);
+        let mut wav = wav_i16(&[1, 2, 3]);
+        wav[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(low_pass(&mut wav, 500.0), Err(WavError::InvalidParameter));
+    }
+}